@@ -0,0 +1,273 @@
+//! Animated GIF, WebP and APNG output for short looping previews.
+
+use anyhow::Context;
+use std::{fmt, num::NonZeroU16, ops::RangeInclusive, path::Path, str::FromStr};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum AnimationFormat {
+    /// 256 colours per frame from a generated palette
+    Gif,
+    /// Lossy animated WebP
+    Webp,
+    /// Lossless animated PNG
+    Apng,
+}
+
+impl fmt::Display for AnimationFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Webp => "webp",
+            AnimationFormat::Apng => "apng",
+        };
+        f.write_str(s)
+    }
+}
+
+impl AnimationFormat {
+    /// Whether `target` has a file extension that fits the format.
+    pub fn matches_target(&self, target: &Path) -> bool {
+        let ext = match target.extension().and_then(|e| e.to_str()) {
+            Some(ext) => ext.to_ascii_lowercase(),
+            None => return false,
+        };
+        match self {
+            AnimationFormat::Apng => ext == "png" || ext == "apng",
+            format => ext == format.to_string(),
+        }
+    }
+}
+
+/// Dithering of the GIF palette, see the ffmpeg `paletteuse` filter.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub enum Dither {
+    /// Ordered 8x8 bayer dithering, the scale (0-5) lowers the visibility of the pattern
+    Bayer(u8),
+    Heckbert,
+    FloydSteinberg,
+    Sierra2,
+    Sierra2_4a,
+    None,
+}
+
+impl fmt::Display for Dither {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dither::Bayer(scale) => write!(f, "bayer:{}", scale),
+            Dither::Heckbert => f.write_str("heckbert"),
+            Dither::FloydSteinberg => f.write_str("floyd-steinberg"),
+            Dither::Sierra2 => f.write_str("sierra2"),
+            Dither::Sierra2_4a => f.write_str("sierra2-4a"),
+            Dither::None => f.write_str("none"),
+        }
+    }
+}
+
+const BAYER_SCALE_RANGE: RangeInclusive<u8> = 0..=5;
+impl FromStr for Dither {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, ':');
+
+        // the unwrap here will always succeed as splitn returns at least 1 element
+        match (split.next().unwrap().trim(), split.next()) {
+            ("bayer", None) => Ok(Dither::Bayer(2)),
+            ("bayer", Some(scale)) => {
+                let scale: u8 = scale.trim().parse().context("bayer scale is not a number")?;
+                if !BAYER_SCALE_RANGE.contains(&scale) {
+                    anyhow::bail!(
+                        "bayer scale out of range: valid range is {} to {}",
+                        BAYER_SCALE_RANGE.start(),
+                        BAYER_SCALE_RANGE.end()
+                    );
+                }
+                Ok(Dither::Bayer(scale))
+            },
+            ("heckbert", None) => Ok(Dither::Heckbert),
+            ("floyd-steinberg", None) => Ok(Dither::FloydSteinberg),
+            ("sierra2", None) => Ok(Dither::Sierra2),
+            ("sierra2-4a", None) => Ok(Dither::Sierra2_4a),
+            ("none", None) => Ok(Dither::None),
+            _ => anyhow::bail!(
+                "unknown dither mode, valid options are: bayer[:SCALE], heckbert, \
+                 floyd-steinberg, sierra2, sierra2-4a, none"
+            ),
+        }
+    }
+}
+
+impl Dither {
+    /// Options of the `paletteuse` filter.
+    fn paletteuse(&self) -> String {
+        match self {
+            Dither::Bayer(scale) => format!("dither=bayer:bayer_scale={}", scale),
+            Dither::Heckbert => String::from("dither=heckbert"),
+            Dither::FloydSteinberg => String::from("dither=floyd_steinberg"),
+            Dither::Sierra2 => String::from("dither=sierra2"),
+            Dither::Sierra2_4a => String::from("dither=sierra2_4a"),
+            Dither::None => String::from("dither=none"),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd)]
+pub struct WebpQuality(pub u8);
+
+const WEBP_QUALITY_RANGE: RangeInclusive<u8> = 0..=100;
+impl WebpQuality {
+    pub fn parse(s: &str) -> anyhow::Result<WebpQuality> {
+        let q: u8 = s.parse().context("webp quality is not a number")?;
+        if WEBP_QUALITY_RANGE.contains(&q) {
+            Ok(WebpQuality(q))
+        } else {
+            anyhow::bail!(
+                "webp quality out of range: valid range is {} to {}",
+                WEBP_QUALITY_RANGE.start(),
+                WEBP_QUALITY_RANGE.end()
+            )
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AnimationSettings {
+    pub format:  AnimationFormat,
+    /// Frame rate of the animation, the input rate if not given
+    pub fps:     Option<NonZeroU16>,
+    /// How often the animation is played, 0 is forever
+    pub plays:   u32,
+    pub dither:  Dither,
+    pub quality: Option<WebpQuality>,
+}
+
+impl AnimationSettings {
+    /// The filters run after scaling, ending in the GIF palette.
+    pub fn filter(&self) -> Option<String> {
+        let mut chain = Vec::new();
+        if let Some(fps) = self.fps {
+            chain.push(format!("fps={}", fps));
+        }
+        if self.format == AnimationFormat::Gif {
+            // a palette generated from the whole animation instead of the generic 256 colours
+            chain.push(format!(
+                "split[pal_in][frames];[pal_in]palettegen=stats_mode=diff[pal];\
+                 [frames][pal]paletteuse={}",
+                self.dither.paletteuse()
+            ));
+        }
+
+        (!chain.is_empty()).then(|| chain.join(","))
+    }
+
+    /// Encoder and muxer options, including `-f`.
+    pub fn args(&self) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        let mut push = |k: &str, v: String| {
+            args.push(k.to_owned());
+            args.push(v);
+        };
+
+        match self.format {
+            AnimationFormat::Gif => {
+                push("-c:v", String::from("gif"));
+                // the gif muxer counts repeats after the first play, -1 plays once
+                let repeats: i64 = match self.plays {
+                    0 => 0,
+                    1 => -1,
+                    plays => plays as i64 - 1,
+                };
+                push("-loop", repeats.to_string());
+                push("-f", String::from("gif"));
+            },
+            AnimationFormat::Webp => {
+                push("-c:v", String::from("libwebp"));
+                push("-pix_fmt", String::from("yuv420p"));
+                if let Some(quality) = self.quality {
+                    push("-quality", quality.0.to_string());
+                }
+                push("-loop", self.plays.to_string());
+                push("-f", String::from("webp"));
+            },
+            AnimationFormat::Apng => {
+                push("-c:v", String::from("apng"));
+                push("-pix_fmt", String::from("rgb24"));
+                push("-plays", self.plays.to_string());
+                push("-f", String::from("apng"));
+            },
+        }
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dither() {
+        assert_eq!("bayer".parse::<Dither>().unwrap(), Dither::Bayer(2));
+        assert_eq!("bayer:0".parse::<Dither>().unwrap(), Dither::Bayer(0));
+        assert_eq!("bayer: 5".parse::<Dither>().unwrap(), Dither::Bayer(5));
+        assert_eq!("sierra2-4a".parse::<Dither>().unwrap(), Dither::Sierra2_4a);
+        assert_eq!("none".parse::<Dither>().unwrap(), Dither::None);
+
+        assert!("bayer:6".parse::<Dither>().is_err());
+        assert!("bayer:x".parse::<Dither>().is_err());
+        assert!("heckbert:1".parse::<Dither>().is_err());
+        assert!("floyd_steinberg".parse::<Dither>().is_err());
+    }
+
+    #[test]
+    fn dither_round_trip() {
+        for s in [
+            "bayer:3",
+            "heckbert",
+            "floyd-steinberg",
+            "sierra2",
+            "sierra2-4a",
+            "none",
+        ] {
+            assert_eq!(s.parse::<Dither>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn parse_webp_quality() {
+        assert_eq!(WebpQuality::parse("0").unwrap(), WebpQuality(0));
+        assert_eq!(WebpQuality::parse("100").unwrap(), WebpQuality(100));
+        assert!(WebpQuality::parse("101").is_err());
+        assert!(WebpQuality::parse("-1").is_err());
+    }
+
+    #[test]
+    fn target_extensions() {
+        assert!(AnimationFormat::Gif.matches_target(Path::new("a.GIF")));
+        assert!(AnimationFormat::Apng.matches_target(Path::new("a.png")));
+        assert!(AnimationFormat::Apng.matches_target(Path::new("a.apng")));
+        assert!(!AnimationFormat::Webp.matches_target(Path::new("a.gif")));
+        assert!(!AnimationFormat::Webp.matches_target(Path::new("webp")));
+    }
+
+    #[test]
+    fn gif_loops() {
+        let settings = |plays| AnimationSettings {
+            format: AnimationFormat::Gif,
+            fps: NonZeroU16::new(12),
+            plays,
+            dither: Dither::Bayer(2),
+            quality: None,
+        };
+        let repeats = |plays| {
+            let args = settings(plays).args();
+            let idx = args.iter().position(|a| a == "-loop").unwrap();
+            args[idx + 1].clone()
+        };
+        assert_eq!(repeats(0), "0");
+        assert_eq!(repeats(1), "-1");
+        assert_eq!(repeats(3), "2");
+
+        assert!(settings(0).filter().unwrap().starts_with("fps=12,split"));
+    }
+}
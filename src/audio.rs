@@ -0,0 +1,593 @@
+//! Audio tracks spliced into the video, each one its own stream in the output.
+
+use anyhow::Context;
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use crate::container::Container;
+
+/// A single audio track, all times are in seconds.
+#[derive(Debug, Clone)]
+pub struct AudioTrack {
+    pub file:       PathBuf,
+    /// Offset of the track into the video
+    pub start:      f64,
+    pub bitrate:    Option<String>,
+    /// Part of the source to use
+    pub trim_start: f64,
+    pub trim_end:   Option<f64>,
+    /// Gain in dB
+    pub volume:     Option<f64>,
+    pub fade_in:    Option<f64>,
+    pub fade_out:   Option<f64>,
+    /// ISO 639-2 language code
+    pub language:   Option<String>,
+    /// Length of the source, only known once probed
+    pub duration:   Option<f64>,
+}
+
+fn seconds(key: &str, value: &str) -> anyhow::Result<f64> {
+    let v: f64 = value
+        .trim()
+        .parse()
+        .with_context(|| format!("`{}` needs a time in seconds", key))?;
+    if v < 0.0 {
+        anyhow::bail!("`{}` can not be negative", key);
+    }
+    Ok(v)
+}
+
+impl FromStr for AudioTrack {
+    type Err = anyhow::Error;
+
+    /// `offset[,bitrate][,key=value...]|file`, see the `--audio` help for the keys.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (options, file) = s
+            .split_once('|')
+            .context("missing file path for audio file")?;
+        let file = file
+            .parse()
+            .context("the given path contains invalid characters")?;
+
+        let mut option_parts = options.split(',');
+
+        // the unwrap here will always succeed as split returns at least 1 element
+        let start = option_parts
+            .next()
+            .unwrap()
+            .trim()
+            .parse()
+            .context("the start time needs to be the offset in seconds")?;
+
+        let mut track = AudioTrack {
+            file,
+            start,
+            bitrate: None,
+            trim_start: 0.0,
+            trim_end: None,
+            volume: None,
+            fade_in: None,
+            fade_out: None,
+            language: None,
+            duration: None,
+        };
+
+        for (idx, part) in option_parts.enumerate() {
+            let (key, value) = match part.split_once('=') {
+                Some(kv) => kv,
+                // the bitrate may be given without a key right after the offset
+                None if idx == 0 => {
+                    track.bitrate = Some(part.trim().to_owned());
+                    continue;
+                },
+                None => anyhow::bail!("expected `key=value`, got `{}`", part),
+            };
+
+            match key.trim() {
+                "bitrate" => track.bitrate = Some(value.trim().to_owned()),
+                "trim" => {
+                    let (from, to) = value
+                        .split_once('-')
+                        .context("`trim` needs a range like `10-70` or `10-`")?;
+                    track.trim_start = seconds("trim", from)?;
+                    if !to.trim().is_empty() {
+                        let end = seconds("trim", to)?;
+                        if end <= track.trim_start {
+                            anyhow::bail!("the trim end has to be after its start");
+                        }
+                        track.trim_end = Some(end);
+                    }
+                },
+                "volume" => {
+                    let db = value.trim();
+                    let db = db
+                        .strip_suffix("dB")
+                        .or_else(|| db.strip_suffix("db"))
+                        .unwrap_or(db);
+                    track.volume = Some(db.trim().parse().context("`volume` needs a gain in dB")?);
+                },
+                "fade-in" => track.fade_in = Some(seconds("fade-in", value)?),
+                "fade-out" => track.fade_out = Some(seconds("fade-out", value)?),
+                "lang" => {
+                    let lang = value.trim();
+                    if lang.len() != 3 || !lang.chars().all(|c| c.is_ascii_alphabetic()) {
+                        anyhow::bail!("`lang` needs a three letter language code like `eng`");
+                    }
+                    track.language = Some(lang.to_ascii_lowercase());
+                },
+                key => anyhow::bail!("unknown audio option `{}`", key),
+            }
+        }
+
+        Ok(track)
+    }
+}
+
+impl AudioTrack {
+    /// Whether the length of the source has to be probed to build the filter.
+    pub fn needs_duration(&self) -> bool { self.fade_out.is_some() && self.trim_end.is_none() }
+
+    /// Whether the track is used as it is, so it can be copied.
+    pub fn is_plain(&self) -> bool {
+        self.start == 0.0
+            && self.trim_start == 0.0
+            && self.trim_end.is_none()
+            && self.volume.is_none()
+            && self.fade_in.is_none()
+            && self.fade_out.is_none()
+    }
+
+    /// Length of the used part of the source.
+    fn length(&self) -> Option<f64> {
+        self.trim_end
+            .or(self.duration)
+            .map(|end| (end - self.trim_start).max(0.0))
+    }
+
+    /// The filter chain taking this track from ffmpeg input `input` to the output `label`.
+    pub fn filter(&self, input: usize, label: &str) -> anyhow::Result<String> {
+        let mut chain = Vec::new();
+
+        if self.trim_start > 0.0 || self.trim_end.is_some() {
+            let mut trim = format!("atrim=start={}", self.trim_start);
+            if let Some(end) = self.trim_end {
+                trim.push_str(&format!(":end={}", end));
+            }
+            chain.push(trim);
+            chain.push(String::from("asetpts=PTS-STARTPTS"));
+        }
+        if let Some(db) = self.volume {
+            chain.push(format!("volume={}dB", db));
+        }
+        if let Some(fade) = self.fade_in {
+            chain.push(format!("afade=t=in:st=0:d={}", fade));
+        }
+        if let Some(fade) = self.fade_out {
+            let length = self
+                .length()
+                .with_context(|| format!("length of {} is unknown", self.file.display()))?;
+            chain.push(format!(
+                "afade=t=out:st={}:d={}",
+                (length - fade).max(0.0),
+                fade
+            ));
+        }
+        chain.push(format!(
+            "adelay={}:all=1",
+            (self.start * 1000.0).trunc() as u64
+        ));
+        chain.push(String::from("apad"));
+
+        Ok(format!("[{}:a:0]{}[{}]", input, chain.join(","), label))
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum AudioCodec {
+    Aac,
+    Opus,
+    Flac,
+    /// Uncompressed 16 bit
+    Pcm,
+    /// Keep the source audio as it is, no trimming, volume, fades or offset possible
+    Copy,
+}
+
+impl fmt::Display for AudioCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "opus",
+            AudioCodec::Flac => "flac",
+            AudioCodec::Pcm => "pcm",
+            AudioCodec::Copy => "copy",
+        };
+        f.write_str(s)
+    }
+}
+
+impl AudioCodec {
+    /// The ffmpeg encoder name, passed to `-c:a`.
+    pub fn encoder(&self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "libopus",
+            AudioCodec::Flac => "flac",
+            AudioCodec::Pcm => "pcm_s16le",
+            AudioCodec::Copy => "copy",
+        }
+    }
+
+    /// The codec name of the resulting stream, as ffprobe reports it.
+    pub fn stream_codec(&self) -> &'static str {
+        match self {
+            AudioCodec::Opus => "opus",
+            codec => codec.encoder(),
+        }
+    }
+
+    fn sample_rates(&self) -> Option<&'static [u32]> {
+        match self {
+            AudioCodec::Aac => Some(&[
+                7350, 8000, 11025, 12000, 16000, 22050, 24000, 32000, 44100, 48000, 64000, 88200,
+                96000,
+            ]),
+            AudioCodec::Opus => Some(&[8000, 12000, 16000, 24000, 48000]),
+            _ => None,
+        }
+    }
+
+    /// Whether a bitrate can be set.
+    pub fn is_lossy(&self) -> bool { matches!(self, AudioCodec::Aac | AudioCodec::Opus) }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum ChannelLayout {
+    Mono,
+    Stereo,
+    #[clap(name = "5.1")]
+    Surround51,
+    #[clap(name = "7.1")]
+    Surround71,
+}
+
+impl ChannelLayout {
+    fn channels(&self) -> u8 {
+        match self {
+            ChannelLayout::Mono => 1,
+            ChannelLayout::Stereo => 2,
+            ChannelLayout::Surround51 => 6,
+            ChannelLayout::Surround71 => 8,
+        }
+    }
+}
+
+impl fmt::Display for ChannelLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ChannelLayout::Mono => "mono",
+            ChannelLayout::Stereo => "stereo",
+            ChannelLayout::Surround51 => "5.1",
+            ChannelLayout::Surround71 => "7.1",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Audio encoder settings shared by all tracks.
+#[derive(Debug, Clone)]
+pub struct AudioSettings {
+    pub codec:       AudioCodec,
+    pub sample_rate: Option<u32>,
+    pub channels:    Option<ChannelLayout>,
+    /// Codec specific quality, aac: vbr quality 0.1-2, opus: compression level 0-10,
+    /// flac: compression level 0-12
+    pub quality:     Option<String>,
+}
+
+impl AudioSettings {
+    /// Check the settings against the tracks, `copied` holds the probed codec of each track
+    /// when copying.
+    pub fn validate(
+        &self,
+        tracks: &[AudioTrack],
+        container: Option<Container>,
+        copied: &[String],
+    ) -> anyhow::Result<()> {
+        let codec = self.codec;
+
+        if codec == AudioCodec::Copy {
+            if self.sample_rate.is_some() || self.channels.is_some() || self.quality.is_some() {
+                anyhow::bail!("copied audio can not be resampled, remixed or re-encoded");
+            }
+            if let Some(track) = tracks.iter().find(|t| !t.is_plain()) {
+                anyhow::bail!(
+                    "{} can not be copied, it is offset, trimmed, faded or has its volume changed",
+                    track.file.display()
+                );
+            }
+        }
+
+        if let Some(track) = tracks.iter().find(|t| t.bitrate.is_some()) {
+            if !codec.is_lossy() {
+                anyhow::bail!(
+                    "{} has a bitrate, but the {} codec does not take one",
+                    track.file.display(),
+                    codec
+                );
+            }
+        }
+
+        if let (Some(rate), Some(valid)) = (self.sample_rate, codec.sample_rates()) {
+            if !valid.contains(&rate) {
+                let valid: Vec<_> = valid.iter().map(u32::to_string).collect();
+                anyhow::bail!(
+                    "the {} codec does not support a sample rate of {}, valid rates are: {}",
+                    codec,
+                    rate,
+                    valid.join(", ")
+                );
+            }
+        }
+
+        if let Some(quality) = self.quality.as_deref() {
+            self.quality_args(quality)?;
+        }
+
+        // without tracks there is no audio stream for the container to hold
+        if let (Some(container), false) = (container, tracks.is_empty()) {
+            let streams: Vec<&str> = match codec {
+                AudioCodec::Copy => copied.iter().map(String::as_str).collect(),
+                codec => vec![codec.stream_codec()],
+            };
+            if let Some(stream) = streams.iter().find(|s| !container.supports_audio(s)) {
+                anyhow::bail!("a {} container can not hold {} audio", container, stream);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn quality_args(&self, quality: &str) -> anyhow::Result<[String; 2]> {
+        let (key, range) = match self.codec {
+            AudioCodec::Aac => ("-q:a", 0.1..=2.0),
+            AudioCodec::Opus => ("-compression_level", 0.0..=10.0),
+            AudioCodec::Flac => ("-compression_level", 0.0..=12.0),
+            codec => anyhow::bail!("the {} codec has no quality setting", codec),
+        };
+
+        let q: f64 = quality
+            .parse()
+            .with_context(|| format!("audio quality `{}` is not a number", quality))?;
+        if !range.contains(&q) {
+            anyhow::bail!(
+                "audio quality out of range: valid range for {} is {} to {}",
+                self.codec,
+                range.start(),
+                range.end()
+            );
+        }
+
+        Ok([key.to_owned(), quality.to_owned()])
+    }
+
+    /// Output options for the audio streams.
+    pub fn args(&self, tracks: &[AudioTrack]) -> Vec<String> {
+        let mut args = vec![String::from("-c:a"), self.codec.encoder().to_owned()];
+        if let Some(rate) = self.sample_rate {
+            args.extend([String::from("-ar"), rate.to_string()]);
+        }
+        if let Some(channels) = self.channels {
+            args.extend([String::from("-ac"), channels.channels().to_string()]);
+        }
+        if let Some(quality) = self.quality.as_deref() {
+            // checked in validate
+            args.extend(self.quality_args(quality).unwrap_or_default());
+        }
+        for (idx, track) in tracks.iter().enumerate() {
+            if let Some(bitrate) = track.bitrate.as_ref() {
+                args.extend([format!("-b:a:{}", idx), bitrate.clone()]);
+            }
+            if let Some(language) = track.language.as_ref() {
+                args.extend([format!("-metadata:s:a:{}", idx), format!("language={}", language)]);
+            }
+        }
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_plain() {
+        let t = "0|music.mp3".parse::<AudioTrack>().unwrap();
+        assert_eq!(t.file, PathBuf::from("music.mp3"));
+        assert_eq!(t.start, 0.0);
+        assert!(t.is_plain());
+
+        let t = "2.5,192k|music.mp3".parse::<AudioTrack>().unwrap();
+        assert_eq!(t.start, 2.5);
+        assert_eq!(t.bitrate.as_deref(), Some("192k"));
+        assert!(!t.is_plain());
+
+        assert!("music.mp3".parse::<AudioTrack>().is_err());
+        assert!("soon|music.mp3".parse::<AudioTrack>().is_err());
+    }
+
+    #[test]
+    fn parse_options() {
+        let t = "0,trim=10-70,volume=-3dB,fade-in=2,fade-out=3,lang=ENG|a.mp3"
+            .parse::<AudioTrack>()
+            .unwrap();
+        assert_eq!(t.trim_start, 10.0);
+        assert_eq!(t.trim_end, Some(70.0));
+        assert_eq!(t.volume, Some(-3.0));
+        assert_eq!(t.fade_in, Some(2.0));
+        assert_eq!(t.fade_out, Some(3.0));
+        assert_eq!(t.language.as_deref(), Some("eng"));
+        assert!(!t.needs_duration());
+
+        let t = "0,trim=10-,fade-out=3|a.mp3".parse::<AudioTrack>().unwrap();
+        assert_eq!(t.trim_end, None);
+        assert!(t.needs_duration());
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("0,trim=70-10|a.mp3".parse::<AudioTrack>().is_err());
+        assert!("0,trim=10-10|a.mp3".parse::<AudioTrack>().is_err());
+        assert!("0,trim=10|a.mp3".parse::<AudioTrack>().is_err());
+        assert!("0,fade-in=-1|a.mp3".parse::<AudioTrack>().is_err());
+        assert!("0,lang=english|a.mp3".parse::<AudioTrack>().is_err());
+        assert!("0,192k,320k|a.mp3".parse::<AudioTrack>().is_err());
+        assert!("0,speed=2|a.mp3".parse::<AudioTrack>().is_err());
+    }
+
+    #[test]
+    fn filter_chain() {
+        let t = "1.5,trim=10-70,volume=-3dB,fade-in=2,fade-out=3|a.mp3"
+            .parse::<AudioTrack>()
+            .unwrap();
+        assert_eq!(
+            t.filter(1, "a1").unwrap(),
+            "[1:a:0]atrim=start=10:end=70,asetpts=PTS-STARTPTS,volume=-3dB,afade=t=in:st=0:d=2,\
+             afade=t=out:st=57:d=3,adelay=1500:all=1,apad[a1]"
+        );
+
+        let mut t = "0,fade-out=3|a.mp3".parse::<AudioTrack>().unwrap();
+        assert!(t.filter(1, "a1").is_err());
+        t.duration = Some(20.0);
+        assert_eq!(
+            t.filter(1, "a1").unwrap(),
+            "[1:a:0]afade=t=out:st=17:d=3,adelay=0:all=1,apad[a1]"
+        );
+    }
+
+    fn settings(codec: AudioCodec) -> AudioSettings {
+        AudioSettings {
+            codec,
+            sample_rate: None,
+            channels: None,
+            quality: None,
+        }
+    }
+
+    #[test]
+    fn validate_sample_rates() {
+        let tracks = ["0|a.wav".parse::<AudioTrack>().unwrap()];
+
+        let opus = AudioSettings {
+            sample_rate: Some(48000),
+            ..settings(AudioCodec::Opus)
+        };
+        assert!(opus.validate(&tracks, None, &[]).is_ok());
+        let opus = AudioSettings {
+            sample_rate: Some(44100),
+            ..settings(AudioCodec::Opus)
+        };
+        assert!(opus.validate(&tracks, None, &[]).is_err());
+
+        // flac and pcm take any rate
+        let flac = AudioSettings {
+            sample_rate: Some(44100),
+            ..settings(AudioCodec::Flac)
+        };
+        assert!(flac.validate(&tracks, None, &[]).is_ok());
+    }
+
+    #[test]
+    fn validate_codecs() {
+        let plain = ["0|a.m4a".parse::<AudioTrack>().unwrap()];
+        let faded = ["0,fade-in=2|a.m4a".parse::<AudioTrack>().unwrap()];
+        let bitrate = ["0,192k|a.wav".parse::<AudioTrack>().unwrap()];
+
+        let copy = settings(AudioCodec::Copy);
+        assert!(copy.validate(&plain, None, &[]).is_ok());
+        assert!(copy.validate(&faded, None, &[]).is_err());
+        let remixed = AudioSettings {
+            channels: Some(ChannelLayout::Mono),
+            ..settings(AudioCodec::Copy)
+        };
+        assert!(remixed.validate(&plain, None, &[]).is_err());
+
+        assert!(settings(AudioCodec::Aac)
+            .validate(&bitrate, None, &[])
+            .is_ok());
+        assert!(settings(AudioCodec::Flac)
+            .validate(&bitrate, None, &[])
+            .is_err());
+
+        let quality = |codec, q: &str| AudioSettings {
+            quality: Some(q.to_owned()),
+            ..settings(codec)
+        };
+        assert!(quality(AudioCodec::Aac, "2")
+            .validate(&plain, None, &[])
+            .is_ok());
+        assert!(quality(AudioCodec::Aac, "3")
+            .validate(&plain, None, &[])
+            .is_err());
+        assert!(quality(AudioCodec::Pcm, "1")
+            .validate(&plain, None, &[])
+            .is_err());
+    }
+
+    #[test]
+    fn validate_containers() {
+        let tracks = ["0|a.wav".parse::<AudioTrack>().unwrap()];
+
+        assert!(settings(AudioCodec::Opus)
+            .validate(&tracks, Some(Container::Webm), &[])
+            .is_ok());
+        assert!(settings(AudioCodec::Aac)
+            .validate(&tracks, Some(Container::Webm), &[])
+            .is_err());
+        assert!(settings(AudioCodec::Pcm)
+            .validate(&tracks, Some(Container::Mp4), &[])
+            .is_err());
+        assert!(settings(AudioCodec::Pcm)
+            .validate(&tracks, Some(Container::Mkv), &[])
+            .is_ok());
+
+        // copied streams are checked by their probed codec
+        let copy = settings(AudioCodec::Copy);
+        assert!(copy
+            .validate(&tracks, Some(Container::Mp4), &[String::from("aac")])
+            .is_ok());
+        assert!(copy
+            .validate(&tracks, Some(Container::Mp4), &[String::from("flac")])
+            .is_err());
+    }
+
+    #[test]
+    fn encoder_args() {
+        let tracks = [
+            "0,192k,lang=eng|a.wav".parse::<AudioTrack>().unwrap(),
+            "0|b.wav".parse::<AudioTrack>().unwrap(),
+        ];
+        let settings = AudioSettings {
+            codec:       AudioCodec::Opus,
+            sample_rate: Some(48000),
+            channels:    Some(ChannelLayout::Surround51),
+            quality:     Some(String::from("10")),
+        };
+        assert_eq!(
+            settings.args(&tracks),
+            vec![
+                "-c:a",
+                "libopus",
+                "-ar",
+                "48000",
+                "-ac",
+                "6",
+                "-compression_level",
+                "10",
+                "-b:a:0",
+                "192k",
+                "-metadata:s:a:0",
+                "language=eng",
+            ]
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use std::ops::RangeInclusive;
+
+use anyhow::Context;
+
+/// Speed preset of libsvtav1, lower is slower and better.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd)]
+pub struct SvtPreset(pub u8);
+
+const SVT_PRESET_RANGE: RangeInclusive<u8> = 0..=13;
+impl SvtPreset {
+    pub fn parse(s: &str) -> anyhow::Result<SvtPreset> {
+        let p: u8 = s.parse().context("svt-av1 preset is not a number")?;
+        if SVT_PRESET_RANGE.contains(&p) {
+            Ok(SvtPreset(p))
+        } else {
+            anyhow::bail!(
+                "svt-av1 preset out of range: valid range is {} to {}",
+                SVT_PRESET_RANGE.start(),
+                SVT_PRESET_RANGE.end()
+            )
+        }
+    }
+}
+
+/// Speed setting of libaom-av1, lower is slower and better.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd)]
+pub struct AomCpuUsed(pub u8);
+
+const AOM_CPU_USED_RANGE: RangeInclusive<u8> = 0..=8;
+impl AomCpuUsed {
+    pub fn parse(s: &str) -> anyhow::Result<AomCpuUsed> {
+        let p: u8 = s.parse().context("cpu-used is not a number")?;
+        if AOM_CPU_USED_RANGE.contains(&p) {
+            Ok(AomCpuUsed(p))
+        } else {
+            anyhow::bail!(
+                "cpu-used out of range: valid range is {} to {}",
+                AOM_CPU_USED_RANGE.start(),
+                AOM_CPU_USED_RANGE.end()
+            )
+        }
+    }
+}
+
+/// Shared by both av1 encoders.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd)]
+pub struct Crf(pub u8);
+
+const CRF_RANGE: RangeInclusive<u8> = 0..=63;
+impl Crf {
+    pub fn parse(s: &str) -> anyhow::Result<Crf> {
+        let p: u8 = s.parse().context("crf is not a number")?;
+        if CRF_RANGE.contains(&p) {
+            Ok(Crf(p))
+        } else {
+            anyhow::bail!(
+                "crf out of range: valid range is {} to {}",
+                CRF_RANGE.start(),
+                CRF_RANGE.end()
+            )
+        }
+    }
+}
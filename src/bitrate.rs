@@ -0,0 +1,118 @@
+//! File size and bitrate arithmetic for size constrained encodes.
+
+use anyhow::Context;
+use std::{fmt, str::FromStr};
+
+/// Share of the target size kept free for the container.
+const CONTAINER_OVERHEAD: f64 = 0.02;
+
+/// Audio bitrate assumed when none is given, ffmpeg's aac default.
+pub const DEFAULT_AUDIO_KBPS: u64 = 128;
+
+/// A size in bytes, written like `49MB`, `1.5GiB` or `800000`.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub struct ByteSize(pub u64);
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}B", self.0) }
+}
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (num, unit) = s.split_at(split);
+
+        let num: f64 = num.parse().context("the size must start with a number")?;
+        let factor = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kb" => 1000,
+            "m" | "mb" => 1000 * 1000,
+            "g" | "gb" => 1000 * 1000 * 1000,
+            "kib" => 1024,
+            "mib" => 1024 * 1024,
+            "gib" => 1024 * 1024 * 1024,
+            unit => anyhow::bail!(
+                "unknown size unit `{}`, valid units are B, KB, MB, GB, KiB, MiB and GiB",
+                unit
+            ),
+        };
+
+        Ok(ByteSize((num * factor as f64) as u64))
+    }
+}
+
+/// Parse an ffmpeg style bitrate like `128k` into kbit/s.
+pub fn parse_kbps(s: &str) -> anyhow::Result<u64> {
+    let s = s.trim().to_ascii_lowercase();
+    let (num, factor) = match s.strip_suffix('k') {
+        Some(num) => (num, 1.0),
+        None => match s.strip_suffix('m') {
+            Some(num) => (num, 1000.0),
+            None => (s.as_str(), 0.001),
+        },
+    };
+    let num: f64 = num
+        .parse()
+        .with_context(|| format!("`{}` is not a bitrate", s))?;
+    Ok((num * factor) as u64)
+}
+
+/// The video bitrate in kbit/s that fills `size` over `duration` seconds next to the audio.
+pub fn video_kbps(size: ByteSize, duration: f64, audio_kbps: u64) -> anyhow::Result<u64> {
+    if duration <= 0.0 {
+        anyhow::bail!("there are no frames to encode");
+    }
+
+    let total_kbps = size.0 as f64 * 8.0 * (1.0 - CONTAINER_OVERHEAD) / 1000.0 / duration;
+    let video_kbps = total_kbps - audio_kbps as f64;
+    if video_kbps < 1.0 {
+        anyhow::bail!(
+            "{} is too small for {:.1} seconds of video, it only allows {:.0} kbit/s in total",
+            size,
+            duration,
+            total_kbps
+        );
+    }
+
+    Ok(video_kbps as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_sizes() {
+        assert_eq!("49MB".parse::<ByteSize>().unwrap(), ByteSize(49_000_000));
+        assert_eq!("1.5GiB".parse::<ByteSize>().unwrap(), ByteSize(1_610_612_736));
+        assert_eq!("800000".parse::<ByteSize>().unwrap(), ByteSize(800_000));
+        assert_eq!(" 10 kb ".parse::<ByteSize>().unwrap(), ByteSize(10_000));
+        assert_eq!("2m".parse::<ByteSize>().unwrap(), ByteSize(2_000_000));
+
+        assert!("MB".parse::<ByteSize>().is_err());
+        assert!("1.2.3MB".parse::<ByteSize>().is_err());
+        assert!("10PB".parse::<ByteSize>().is_err());
+    }
+
+    #[test]
+    fn parse_bitrates() {
+        assert_eq!(parse_kbps("128k").unwrap(), 128);
+        assert_eq!(parse_kbps("6M").unwrap(), 6000);
+        assert_eq!(parse_kbps("4500000").unwrap(), 4500);
+        assert!(parse_kbps("fast").is_err());
+    }
+
+    #[test]
+    fn video_bitrate() {
+        assert_eq!(video_kbps(ByteSize(10_000_000), 10.0, 128).unwrap(), 7712);
+        assert_eq!(video_kbps(ByteSize(10_000_000), 10.0, 0).unwrap(), 7840);
+
+        assert!(video_kbps(ByteSize(10_000_000), 0.0, 128).is_err());
+        assert!(video_kbps(ByteSize(1000), 60.0, 128).is_err());
+    }
+}
@@ -0,0 +1,194 @@
+//! Output pixel format and colour metadata.
+//!
+//! Frames are RGB, so the conversion to YUV has to use the same matrix and range the output is
+//! tagged with, otherwise players show shifted colours.
+
+use std::fmt;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum PixelFormat {
+    Yuv420p,
+    Yuv422p,
+    Yuv444p,
+    Yuv420p10le,
+    Yuv422p10le,
+    Yuv444p10le,
+}
+
+impl fmt::Display for PixelFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            PixelFormat::Yuv420p => "yuv420p",
+            PixelFormat::Yuv422p => "yuv422p",
+            PixelFormat::Yuv444p => "yuv444p",
+            PixelFormat::Yuv420p10le => "yuv420p10le",
+            PixelFormat::Yuv422p10le => "yuv422p10le",
+            PixelFormat::Yuv444p10le => "yuv444p10le",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum ColorRange {
+    /// 16-235, what almost every player expects
+    Limited,
+    /// 0-255
+    Full,
+}
+
+impl fmt::Display for ColorRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ColorRange::Limited => "limited",
+            ColorRange::Full => "full",
+        };
+        f.write_str(s)
+    }
+}
+
+impl ColorRange {
+    fn ffmpeg(&self) -> &'static str {
+        match self {
+            ColorRange::Limited => "tv",
+            ColorRange::Full => "pc",
+        }
+    }
+}
+
+/// Colour primaries, transfer and matrix, always set together.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum ColorSpace {
+    /// HD video
+    Bt709,
+    /// SD video
+    Bt601,
+    /// UHD video, SDR transfer
+    Bt2020,
+}
+
+impl fmt::Display for ColorSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ColorSpace::Bt709 => "bt709",
+            ColorSpace::Bt601 => "bt601",
+            ColorSpace::Bt2020 => "bt2020",
+        };
+        f.write_str(s)
+    }
+}
+
+impl ColorSpace {
+    /// Name of the matrix for the scale filter.
+    fn matrix(&self) -> &'static str {
+        match self {
+            ColorSpace::Bt709 => "bt709",
+            ColorSpace::Bt601 => "bt601",
+            ColorSpace::Bt2020 => "bt2020",
+        }
+    }
+
+    /// `-colorspace`, `-color_primaries` and `-color_trc` values.
+    fn tags(&self) -> [&'static str; 3] {
+        match self {
+            ColorSpace::Bt709 => ["bt709", "bt709", "bt709"],
+            ColorSpace::Bt601 => ["smpte170m", "smpte170m", "smpte170m"],
+            ColorSpace::Bt2020 => ["bt2020nc", "bt2020", "bt2020-10"],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ColorSettings {
+    pub space: ColorSpace,
+    pub range: ColorRange,
+}
+
+impl ColorSettings {
+    /// The filter converting RGB frames to `pix_fmt` with the tagged matrix and range.
+    pub fn conversion(&self, pix_fmt: &str) -> String {
+        format!(
+            "scale=out_color_matrix={}:out_range={},format={}",
+            self.space.matrix(),
+            self.range.ffmpeg(),
+            pix_fmt
+        )
+    }
+
+    /// ffmpeg output options tagging the stream.
+    pub fn args(&self) -> Vec<String> {
+        let [matrix, primaries, trc] = self.space.tags();
+        [
+            "-colorspace",
+            matrix,
+            "-color_primaries",
+            primaries,
+            "-color_trc",
+            trc,
+            "-color_range",
+            self.range.ffmpeg(),
+        ]
+        .iter()
+        .map(|s| s.to_string())
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversion_filter() {
+        let color = ColorSettings {
+            space: ColorSpace::Bt709,
+            range: ColorRange::Limited,
+        };
+        assert_eq!(
+            color.conversion("yuv420p"),
+            "scale=out_color_matrix=bt709:out_range=tv,format=yuv420p"
+        );
+
+        let color = ColorSettings {
+            space: ColorSpace::Bt601,
+            range: ColorRange::Full,
+        };
+        assert_eq!(
+            color.conversion("yuv444p10le"),
+            "scale=out_color_matrix=bt601:out_range=pc,format=yuv444p10le"
+        );
+    }
+
+    #[test]
+    fn tag_args() {
+        let color = ColorSettings {
+            space: ColorSpace::Bt601,
+            range: ColorRange::Limited,
+        };
+        assert_eq!(color.args(), [
+            "-colorspace",
+            "smpte170m",
+            "-color_primaries",
+            "smpte170m",
+            "-color_trc",
+            "smpte170m",
+            "-color_range",
+            "tv",
+        ]);
+
+        let color = ColorSettings {
+            space: ColorSpace::Bt2020,
+            range: ColorRange::Full,
+        };
+        assert_eq!(color.args(), [
+            "-colorspace",
+            "bt2020nc",
+            "-color_primaries",
+            "bt2020",
+            "-color_trc",
+            "bt2020-10",
+            "-color_range",
+            "pc",
+        ]);
+    }
+}
@@ -0,0 +1,434 @@
+//! Layered settings: config files fill in every option that was not given on the command line.
+//!
+//! Files are read in order of increasing precedence: the user config, `vidgen.toml` in the
+//! source directory and the file passed with `--config`. Top level keys are the long option
+//! names, named profiles live in `[profiles.NAME]` tables and are applied over the top level
+//! keys when selected with `--profile`. A setting drops the ones of lower layers it conflicts
+//! with, like `quality` does with `crf`.
+
+use anyhow::Context;
+use clap::{Arg, ArgMatches, Command, ValueSource};
+use std::{
+    ffi::OsString,
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+use toml::{value::Table, Value};
+
+/// Name of the per source directory config file.
+pub const SOURCE_CONFIG: &str = "vidgen.toml";
+
+const PROFILES: &str = "profiles";
+
+/// Options that only make sense on the command line.
+const CLI_ONLY: &[&str] = &["help", "version", "config", "profile", "print-config"];
+
+/// Options only read by the x264 encoder, superseded by choosing another encoder.
+const X264_ONLY: &[&str] = &["crf", "rate-control", "x264-preset", "x264-tune"];
+
+fn user_config() -> Option<PathBuf> {
+    #[cfg(windows)]
+    let base = std::env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(windows))]
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")));
+
+    base.map(|b| b.join(env!("CARGO_PKG_NAME")).join("config.toml"))
+}
+
+fn read_table(path: &Path) -> anyhow::Result<Table> {
+    let data = std::fs::read_to_string(path).context("failed to read config file")?;
+    toml::from_str(&data).context("failed to parse config file")
+}
+
+/// Merge the settings of a higher layer `top` into `base`. A setting replaces the same one in
+/// `base` and drops the ones there that it conflicts with or supersedes, profiles are merged by
+/// name the same way. Clashes within `top` are left for clap to report.
+fn merge(command: &Command, base: &mut Table, top: Table) {
+    let overrides = |arg: &Arg| {
+        top.iter().any(|(key, value)| {
+            find_arg(command, key).is_some_and(|given| {
+                supersedes(command, given, scalar(key, value).ok().as_deref(), arg)
+            })
+        })
+    };
+    let dropped: Vec<String> = base
+        .keys()
+        .filter(|key| find_arg(command, key).is_some_and(&overrides))
+        .cloned()
+        .collect();
+    for key in dropped {
+        base.remove(&key);
+    }
+
+    for (key, value) in top {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(profiles)), Value::Table(top)) if key == PROFILES => {
+                for (name, profile) in top {
+                    match (profiles.get_mut(&name), profile) {
+                        (Some(Value::Table(b)), Value::Table(t)) => merge(command, b, t),
+                        (_, profile) => {
+                            profiles.insert(name, profile);
+                        },
+                    }
+                }
+            },
+            (_, value) => {
+                base.insert(key, value);
+            },
+        }
+    }
+}
+
+fn scalar(key: &str, value: &Value) -> anyhow::Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Integer(i) => Ok(i.to_string()),
+        Value::Float(f) => Ok(f.to_string()),
+        Value::Boolean(b) => Ok(b.to_string()),
+        _ => anyhow::bail!("`{}` must be a string, number or boolean", key),
+    }
+}
+
+/// Turn a setting into command line arguments for the option `long`.
+fn to_args(command: &Command, key: &str, value: &Value) -> anyhow::Result<Vec<OsString>> {
+    let arg = command
+        .get_arguments()
+        .find(|a| a.get_long() == Some(key) && !CLI_ONLY.contains(&key))
+        .with_context(|| format!("unknown setting `{}`", key))?;
+    let flag = format!("--{}", key);
+
+    if !arg.is_takes_value_set() {
+        return match value {
+            Value::Boolean(true) => Ok(vec![flag.into()]),
+            Value::Boolean(false) => Ok(Vec::new()),
+            _ => anyhow::bail!("`{}` is a switch and must be true or false", key),
+        };
+    }
+
+    let values = match value {
+        Value::Array(values) => values
+            .iter()
+            .map(|v| scalar(key, v))
+            .collect::<anyhow::Result<Vec<_>>>()?,
+        value => vec![scalar(key, value)?],
+    };
+
+    Ok(values
+        .into_iter()
+        .map(|v| OsString::from(format!("{}={}", flag, v)))
+        .collect())
+}
+
+fn find_arg<'a, 'help>(command: &'a Command<'help>, long: &str) -> Option<&'a Arg<'help>> {
+    command.get_arguments().find(|a| a.get_long() == Some(long))
+}
+
+/// Whether setting `given` overrides `arg`: it is the same option, the two conflict, or `given`
+/// picks an encoder other than x264 and `arg` is only read by x264. `value` is what `given` is
+/// set to, it only matters for the encoder.
+fn supersedes(command: &Command, given: &Arg, value: Option<&str>, arg: &Arg) -> bool {
+    let conflicts = |a: &Arg, b: &Arg| {
+        command
+            .get_arg_conflicts_with(a)
+            .iter()
+            .any(|c| c.get_id() == b.get_id())
+    };
+
+    given.get_id() == arg.get_id()
+        || conflicts(given, arg)
+        || conflicts(arg, given)
+        || (given.get_id() == "encoder"
+            && value != Some("x264")
+            && arg.get_long().is_some_and(|long| X264_ONLY.contains(&long)))
+}
+
+/// Whether the command line already decides `arg`: it was given there, or an option that
+/// conflicts with or supersedes it was.
+fn decided_by_cli(command: &Command, cli: &ArgMatches, arg: &Arg) -> bool {
+    command
+        .get_arguments()
+        .filter(|given| cli.value_source(given.get_id()) == Some(ValueSource::CommandLine))
+        .any(|given| supersedes(command, given, cli.value_of("encoder"), arg))
+}
+
+/// Parse the command line, filling in everything not given there from the config files.
+pub fn load(command: Command) -> anyhow::Result<ArgMatches> {
+    load_from(command, std::env::args_os().collect(), user_config())
+}
+
+fn load_from(
+    command: Command,
+    argv: Vec<OsString>,
+    user_config: Option<PathBuf>,
+) -> anyhow::Result<ArgMatches> {
+    let cli = command.clone().get_matches_from(&argv);
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    paths.extend(user_config);
+    if let Some(source) = cli.value_of("source") {
+        paths.push(Path::new(source).join(SOURCE_CONFIG));
+    }
+    let explicit = cli.value_of("config").map(PathBuf::from);
+    paths.extend(explicit.clone());
+
+    let mut table = Table::new();
+    for path in paths {
+        if !path.exists() {
+            if Some(&path) == explicit.as_ref() {
+                anyhow::bail!("config file {} does not exist", path.display());
+            }
+            continue;
+        }
+
+        let file = read_table(&path).with_context(|| format!("in {}", path.display()))?;
+        merge(&command, &mut table, file);
+    }
+
+    let profiles = match table.remove(PROFILES) {
+        Some(Value::Table(profiles)) => profiles,
+        Some(_) => anyhow::bail!("`{}` must be a table of named profiles", PROFILES),
+        None => Table::new(),
+    };
+
+    if let Some(name) = cli.value_of("profile") {
+        match profiles.get(name) {
+            Some(Value::Table(profile)) => merge(&command, &mut table, profile.clone()),
+            Some(_) => anyhow::bail!("profile `{}` must be a table", name),
+            None => anyhow::bail!("profile `{}` is not defined in any config file", name),
+        }
+    }
+
+    let mut extra = Vec::new();
+    for (key, value) in &table {
+        if let Some(arg) = find_arg(&command, key) {
+            if decided_by_cli(&command, &cli, arg) {
+                continue;
+            }
+        }
+
+        extra.extend(
+            to_args(&command, key, value).with_context(|| format!("in setting `{}`", key))?,
+        );
+    }
+
+    if extra.is_empty() {
+        return Ok(cli);
+    }
+
+    // insert the settings before the user given ones, so a `--` does not swallow them
+    let mut args = argv;
+    args.splice(1..1, extra);
+    Ok(command.get_matches_from(args))
+}
+
+/// Render the settings that were given on the command line or in a config file as a config
+/// file. Defaults are left out, some of them are only accepted together with another option.
+pub fn render(command: &Command, matches: &ArgMatches) -> String {
+    fn quote(s: &str) -> String { Value::String(s.to_owned()).to_string() }
+
+    let mut out = String::new();
+    for arg in command.get_arguments() {
+        let long = match arg.get_long() {
+            Some(long) if !CLI_ONLY.contains(&long) => long,
+            _ => continue,
+        };
+        let id = arg.get_id();
+        if matches.value_source(id) != Some(ValueSource::CommandLine) {
+            continue;
+        }
+
+        if !arg.is_takes_value_set() {
+            let _ = writeln!(out, "{} = true", long);
+            continue;
+        }
+
+        let values: Vec<_> = match matches.values_of(id) {
+            Some(values) => values.map(quote).collect(),
+            None => continue,
+        };
+        if arg.is_multiple_occurrences_set() || arg.is_multiple_values_set() {
+            let _ = writeln!(out, "{} = [{}]", long, values.join(", "));
+        } else {
+            let _ = writeln!(out, "{} = {}", long, values.join(", "));
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::CommandFactory;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("vidgen-config-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn argv(dir: &Path, args: &[&str]) -> Vec<OsString> {
+        let mut argv = vec![OsString::from("vidgen"), dir.into(), "out.mp4".into()];
+        argv.extend(args.iter().map(OsString::from));
+        argv
+    }
+
+    #[test]
+    fn printed_config_loads() {
+        let dir = temp_dir("print");
+        let command = crate::Args::command();
+
+        let given = [
+            "--fps",
+            "30",
+            "--animation",
+            "gif",
+            "--follow",
+            "--thumbnail",
+            "1",
+        ];
+        let matches = load_from(command.clone(), argv(&dir, &given), None).unwrap();
+        let printed = render(&command, &matches);
+        assert!(printed.contains("fps = \"30\"\n"));
+        assert!(printed.contains("follow = true\n"));
+        assert!(printed.contains("thumbnail = [\"1\"]\n"));
+        // defaults are left out, `loop` is only accepted together with `--animation`
+        assert!(!printed.contains("loop"));
+        assert!(!printed.contains("scale-mode"));
+
+        let saved = dir.join("saved.toml");
+        std::fs::write(&saved, &printed).unwrap();
+        let empty = temp_dir("print-empty");
+        let config = ["--config", saved.to_str().unwrap()];
+        let loaded = load_from(command.clone(), argv(&empty, &config), None).unwrap();
+        assert_eq!(render(&command, &loaded), printed);
+
+        // without the flag it depends on, a default must not be printed either
+        let matches = load_from(command.clone(), argv(&dir, &[]), None).unwrap();
+        let printed = render(&command, &matches);
+        std::fs::write(&saved, &printed).unwrap();
+        assert!(load_from(command.clone(), argv(&empty, &config), None).is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&empty).unwrap();
+    }
+
+    fn table(s: &str) -> Table { toml::from_str(s).unwrap() }
+
+    #[test]
+    fn merge_drops_superseded() {
+        let command = crate::Args::command();
+
+        let mut base = table("crf = 20\nx264-preset = \"slow\"\nfps = 30\n");
+        merge(&command, &mut base, table("quality = \"30\"\n"));
+        assert_eq!(
+            base,
+            table("x264-preset = \"slow\"\nfps = 30\nquality = \"30\"\n")
+        );
+
+        let mut base = table("crf = 20\nx264-preset = \"slow\"\nfps = 30\n");
+        merge(&command, &mut base, table("encoder = \"vp9\"\n"));
+        assert_eq!(base, table("fps = 30\nencoder = \"vp9\"\n"));
+
+        // x264 keeps its options
+        let mut base = table("crf = 20\n");
+        merge(&command, &mut base, table("encoder = \"x264\"\n"));
+        assert_eq!(base, table("crf = 20\nencoder = \"x264\"\n"));
+
+        // clashes within one layer are kept
+        let mut base = Table::new();
+        merge(&command, &mut base, table("crf = 20\nquality = \"30\"\n"));
+        assert_eq!(base, table("crf = 20\nquality = \"30\"\n"));
+    }
+
+    #[test]
+    fn merge_profiles() {
+        let command = crate::Args::command();
+
+        let mut base =
+            table("fps = 30\n[profiles.a]\ncrf = 20\nfps = 24\n[profiles.b]\nfps = 12\n");
+        merge(
+            &command,
+            &mut base,
+            table("[profiles.a]\nquality = \"25\"\n"),
+        );
+        assert_eq!(
+            base,
+            table("fps = 30\n[profiles.a]\nfps = 24\nquality = \"25\"\n[profiles.b]\nfps = 12\n")
+        );
+    }
+
+    #[test]
+    fn decided_by_command_line() {
+        let command = crate::Args::command();
+        let dir = temp_dir("decided");
+        let given = ["--quality", "30", "--encoder", "vp9"];
+        let cli = command.clone().get_matches_from(argv(&dir, &given));
+        let decided =
+            |long: &str| decided_by_cli(&command, &cli, find_arg(&command, long).unwrap());
+
+        assert!(decided("quality"));
+        assert!(decided("encoder"));
+        // conflicts with `--quality`
+        assert!(decided("crf"));
+        assert!(decided("target-size"));
+        // only read by x264
+        assert!(decided("x264-preset"));
+        assert!(!decided("fps"));
+        assert!(!decided("preset"));
+
+        let cli = command
+            .clone()
+            .get_matches_from(argv(&dir, &["--encoder", "x264"]));
+        assert!(!decided_by_cli(
+            &command,
+            &cli,
+            find_arg(&command, "crf").unwrap()
+        ));
+
+        // `--preset` replaces `--x264-preset` given in a config file
+        let cli = command
+            .clone()
+            .get_matches_from(argv(&dir, &["--preset", "slow"]));
+        assert!(decided_by_cli(
+            &command,
+            &cli,
+            find_arg(&command, "x264-preset").unwrap()
+        ));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn profiles_override_config() {
+        let dir = temp_dir("layers");
+        let command = crate::Args::command();
+        std::fs::write(
+            dir.join(SOURCE_CONFIG),
+            "crf = 20\nfps = 30\n[profiles.web]\nquality = \"30\"\n[profiles.vp9]\nencoder = \
+             \"vp9\"\n",
+        )
+        .unwrap();
+
+        let matches = load_from(command.clone(), argv(&dir, &["--profile", "web"]), None).unwrap();
+        assert_eq!(matches.value_of("quality"), Some("30"));
+        assert_eq!(matches.value_of("crf"), None);
+        assert_eq!(matches.value_of("fps"), Some("30"));
+
+        let matches = load_from(command.clone(), argv(&dir, &["--profile", "vp9"]), None).unwrap();
+        assert_eq!(matches.value_of("encoder"), Some("vp9"));
+        assert_eq!(matches.value_of("crf"), None);
+
+        // the source config is a higher layer than the user config
+        let user = dir.join("user.toml");
+        std::fs::write(&user, "quality = \"40\"\n").unwrap();
+        let matches = load_from(command.clone(), argv(&dir, &[]), Some(user)).unwrap();
+        assert_eq!(matches.value_of("crf"), Some("20"));
+        assert_eq!(matches.value_of("quality"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+//! Output containers and what they can hold.
+
+use std::{fmt, path::Path};
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum Container {
+    Mp4,
+    Mov,
+    Mkv,
+    Webm,
+}
+
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Container::Mp4 => "mp4",
+            Container::Mov => "mov",
+            Container::Mkv => "mkv",
+            Container::Webm => "webm",
+        };
+        f.write_str(s)
+    }
+}
+
+impl Container {
+    /// Guess the container from the extension of the target, like ffmpeg would.
+    pub fn from_target(target: &Path) -> Option<Self> {
+        Self::from_extension(target.extension()?.to_str()?)
+    }
+
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "mp4" | "m4v" => Some(Container::Mp4),
+            "mov" => Some(Container::Mov),
+            "mkv" => Some(Container::Mkv),
+            "webm" => Some(Container::Webm),
+            _ => None,
+        }
+    }
+
+    /// The ffmpeg muxer name, passed to `-f`.
+    pub fn muxer(&self) -> &'static str {
+        match self {
+            Container::Mp4 => "mp4",
+            Container::Mov => "mov",
+            Container::Mkv => "matroska",
+            Container::Webm => "webm",
+        }
+    }
+
+    /// Muxer options always used for the container.
+    pub fn args(&self) -> &'static [&'static str] {
+        match self {
+            // move the index to the front so players can start before the download is done
+            Container::Mp4 => &["-movflags", "+faststart"],
+            _ => &[],
+        }
+    }
+
+    /// Whether the container can hold a video stream of the given ffmpeg codec name.
+    pub fn supports_video(&self, codec: &str) -> bool {
+        match self {
+            Container::Mp4 => matches!(codec, "h264" | "hevc" | "av1" | "vp9" | "mpeg4"),
+            Container::Mov => {
+                matches!(codec, "h264" | "hevc" | "prores" | "mpeg4" | "png" | "qtrle")
+            },
+            Container::Mkv => true,
+            Container::Webm => matches!(codec, "vp9" | "av1"),
+        }
+    }
+
+    /// Whether the container can hold an audio stream of the given ffmpeg codec name.
+    pub fn supports_audio(&self, codec: &str) -> bool {
+        match self {
+            Container::Mp4 => matches!(codec, "aac" | "mp3" | "alac" | "opus" | "ac3" | "eac3"),
+            Container::Mov => {
+                codec.starts_with("pcm_")
+                    || matches!(codec, "aac" | "mp3" | "alac" | "ac3" | "eac3")
+            },
+            Container::Mkv => true,
+            Container::Webm => matches!(codec, "opus" | "vorbis"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_target() {
+        assert_eq!(
+            Container::from_target(Path::new("out.mp4")),
+            Some(Container::Mp4)
+        );
+        assert_eq!(
+            Container::from_target(Path::new("out.M4V")),
+            Some(Container::Mp4)
+        );
+        assert_eq!(
+            Container::from_target(Path::new("a/b.webm")),
+            Some(Container::Webm)
+        );
+        assert_eq!(Container::from_target(Path::new("out.avi")), None);
+        assert_eq!(Container::from_target(Path::new("out")), None);
+    }
+
+    #[test]
+    fn faststart() {
+        assert_eq!(Container::Mp4.args(), ["-movflags", "+faststart"]);
+        assert!(Container::Mov.args().is_empty());
+        assert!(Container::Mkv.args().is_empty());
+        assert_eq!(Container::Mkv.muxer(), "matroska");
+    }
+
+    #[test]
+    fn supported_codecs() {
+        assert!(Container::Mp4.supports_video("h264"));
+        assert!(!Container::Mp4.supports_video("prores"));
+        assert!(Container::Mov.supports_video("prores"));
+        assert!(!Container::Webm.supports_video("h264"));
+        assert!(Container::Mkv.supports_video("ffv1"));
+
+        assert!(Container::Mov.supports_audio("pcm_s16le"));
+        assert!(!Container::Mp4.supports_audio("pcm_s16le"));
+        assert!(!Container::Webm.supports_audio("aac"));
+        assert!(Container::Mkv.supports_audio("flac"));
+    }
+}
@@ -0,0 +1,152 @@
+//! Frame size validation. ffmpeg is told a single input size for the whole pipe, so every frame
+//! has to match it.
+
+use anyhow::Context;
+use image::{imageops::FilterType, DynamicImage, ImageError, ImageFormat, ImageOutputFormat};
+use std::{fmt, io::Cursor};
+
+use crate::framelist::Frame;
+
+/// How frames that do not match the input size are handled.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum SizeMismatch {
+    /// Fail before encoding, listing the offending frames
+    Error,
+    /// Scale offending frames to the input size while streaming
+    Normalize,
+    /// Do not check frame sizes
+    Ignore,
+}
+
+impl fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            SizeMismatch::Error => "error",
+            SizeMismatch::Normalize => "normalize",
+            SizeMismatch::Ignore => "ignore",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug)]
+pub struct Mismatch {
+    pub fid:  u64,
+    pub size: (u32, u32),
+}
+
+/// Read the header of every frame and return those whose size differs from `expected`.
+///
+/// Frames in a format that can not be inspected are skipped with a warning.
+pub async fn scan(frames: &[Frame], expected: (u32, u32)) -> anyhow::Result<Vec<Mismatch>> {
+    let frames = frames.to_vec();
+    tokio::task::spawn_blocking(move || {
+        let mut mismatches = Vec::new();
+        let mut warned = false;
+        for Frame(fid, path) in frames {
+            let size = match image::image_dimensions(&path) {
+                Ok(size) => size,
+                Err(ImageError::Unsupported(why)) => {
+                    if !warned {
+                        warn!(
+                            frame=%path.display(),
+                            %why,
+                            "unable to check frame sizes of this format"
+                        );
+                        warned = true;
+                    }
+                    continue;
+                },
+                Err(why) => {
+                    return Err(why)
+                        .with_context(|| format!("failed to read header of {}", path.display()))
+                },
+            };
+
+            if size != expected {
+                mismatches.push(Mismatch { fid, size });
+            }
+        }
+        Ok(mismatches)
+    })
+    .await
+    .context("frame size scan failed")?
+}
+
+pub fn report(mismatches: &[Mismatch], expected: (u32, u32)) -> String {
+    const SHOWN: usize = 10;
+
+    let mut list: Vec<String> = mismatches
+        .iter()
+        .take(SHOWN)
+        .map(|m| format!("{} ({}x{})", m.fid, m.size.0, m.size.1))
+        .collect();
+    if mismatches.len() > SHOWN {
+        list.push(format!("and {} more", mismatches.len() - SHOWN));
+    }
+
+    format!(
+        "{} frames are not {}x{}: {}",
+        mismatches.len(),
+        expected.0,
+        expected.1,
+        list.join(", ")
+    )
+}
+
+/// Scale an encoded frame to `size`, keeping its format. Returns `None` if it already fits.
+///
+/// Frames in a format that can not be inspected are passed through like [`scan`] skips them.
+pub fn normalize(data: &[u8], size: (u32, u32)) -> anyhow::Result<Option<Vec<u8>>> {
+    let format = match image::guess_format(data) {
+        Ok(format) => format,
+        Err(ImageError::Unsupported(why)) => {
+            debug!(%why, "unable to normalize frames of this format");
+            return Ok(None);
+        },
+        Err(why) => return Err(why).context("failed to detect frame format"),
+    };
+    // only the header is read for frames that already fit
+    let header = image::io::Reader::with_format(Cursor::new(data), format).into_dimensions();
+    if matches!(header, Ok(dimensions) if dimensions == size) {
+        return Ok(None);
+    }
+
+    let image = match image::load_from_memory_with_format(data, format) {
+        Ok(image) => image,
+        Err(ImageError::Unsupported(why)) => {
+            debug!(%why, "unable to normalize frames of this format");
+            return Ok(None);
+        },
+        Err(why) => return Err(why).context("failed to decode frame"),
+    };
+    if (image.width(), image.height()) == size {
+        return Ok(None);
+    }
+
+    let image = image.resize_exact(size.0, size.1, FilterType::CatmullRom);
+    let (image, output) = match format {
+        // jpeg has no alpha channel
+        ImageFormat::Jpeg => {
+            let image = DynamicImage::ImageRgb8(image.into_rgb8());
+            (image, ImageOutputFormat::Jpeg(95))
+        },
+        format => (image, ImageOutputFormat::from(format)),
+    };
+
+    let mut out = Cursor::new(Vec::new());
+    image
+        .write_to(&mut out, output)
+        .context("failed to encode normalized frame")?;
+    Ok(Some(out.into_inner()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_passes_unknown_formats() {
+        assert_eq!(normalize(b"not a frame", (4, 4)).unwrap(), None);
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::Context;
+use std::{fmt, path::PathBuf, str::FromStr};
+use tokio::fs;
+
+use crate::framelist::Frame;
+
+/// What to do with a frame once it has been streamed to ffmpeg.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum FrameDisposal {
+    /// Remove the frame, optionally only warning if that fails
+    Delete { ignore_errors: bool },
+    /// Leave the frame where it is
+    Keep,
+    /// Move the frame into the given directory
+    Move(PathBuf),
+    /// Append the frame to the given tar archive and remove it afterwards
+    Tar(PathBuf),
+}
+
+impl Default for FrameDisposal {
+    fn default() -> Self {
+        FrameDisposal::Delete {
+            ignore_errors: false,
+        }
+    }
+}
+
+impl fmt::Display for FrameDisposal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameDisposal::Delete {
+                ignore_errors: false,
+            } => f.write_str("delete"),
+            FrameDisposal::Delete {
+                ignore_errors: true,
+            } => f.write_str("delete-no-error"),
+            FrameDisposal::Keep => f.write_str("keep"),
+            FrameDisposal::Move(dir) => write!(f, "move:{}", dir.display()),
+            FrameDisposal::Tar(file) => write!(f, "tar:{}", file.display()),
+        }
+    }
+}
+
+impl FromStr for FrameDisposal {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, ':');
+
+        // the unwrap here will always succeed as splitn returns at least 1 element
+        let kind = split.next().unwrap().trim();
+        let path = split.next().map(PathBuf::from);
+
+        match (kind, path) {
+            ("delete", None) => Ok(FrameDisposal::Delete {
+                ignore_errors: false,
+            }),
+            ("delete-no-error", None) => Ok(FrameDisposal::Delete {
+                ignore_errors: true,
+            }),
+            ("keep", None) => Ok(FrameDisposal::Keep),
+            ("move", Some(dir)) => Ok(FrameDisposal::Move(dir)),
+            ("tar", Some(file)) => Ok(FrameDisposal::Tar(file)),
+            ("move", None) | ("tar", None) => {
+                anyhow::bail!("`{}` requires a path (example: `{}:./frames`)", kind, kind)
+            },
+            _ => anyhow::bail!(
+                "unknown frame disposal, valid options are: delete, delete-no-error, keep, \
+                 move:DIR, tar:FILE"
+            ),
+        }
+    }
+}
+
+impl FrameDisposal {
+    /// Prepare any output needed by the policy (target directory, archive file).
+    pub async fn open(self) -> anyhow::Result<Disposer> {
+        match self {
+            FrameDisposal::Delete { ignore_errors } => Ok(Disposer::Delete { ignore_errors }),
+            FrameDisposal::Keep => Ok(Disposer::Keep),
+            FrameDisposal::Move(dir) => {
+                fs::create_dir_all(&dir)
+                    .await
+                    .context("failed to create frame move directory")?;
+                Ok(Disposer::Move(dir))
+            },
+            FrameDisposal::Tar(file) => {
+                let handle = fs::File::create(&file)
+                    .await
+                    .context("failed to create frame archive")?
+                    .into_std()
+                    .await;
+                Ok(Disposer::Tar(Some(tar::Builder::new(handle))))
+            },
+        }
+    }
+}
+
+/// The opened form of a [`FrameDisposal`], used by the runner for each frame.
+pub enum Disposer {
+    Delete { ignore_errors: bool },
+    Keep,
+    Move(PathBuf),
+    /// Only empty while a frame is appended on the blocking pool
+    Tar(Option<tar::Builder<std::fs::File>>),
+}
+
+impl fmt::Debug for Disposer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Disposer::Delete { ignore_errors } => f
+                .debug_struct("Delete")
+                .field("ignore_errors", ignore_errors)
+                .finish(),
+            Disposer::Keep => f.write_str("Keep"),
+            Disposer::Move(dir) => f.debug_tuple("Move").field(dir).finish(),
+            Disposer::Tar(_) => f.write_str("Tar"),
+        }
+    }
+}
+
+impl Disposer {
+    pub async fn dispose(&mut self, frame: &Frame) -> anyhow::Result<()> {
+        match self {
+            Disposer::Delete { ignore_errors } => {
+                let rmfr = fs::remove_file(&frame.1)
+                    .await
+                    .context("failed to remove frame");
+
+                if let Err(why) = rmfr {
+                    if *ignore_errors {
+                        warn!(n=%frame.0, e=?why, "failed to delete frame");
+                    } else {
+                        return Err(why);
+                    }
+                }
+            },
+            Disposer::Keep => {},
+            Disposer::Move(dir) => {
+                let name = frame.1.file_name().context("frame has no file name")?;
+                let target = dir.join(name);
+
+                // rename does not work across filesystems, fall back to copying
+                if let Err(why) = fs::rename(&frame.1, &target).await {
+                    debug!(e=?why, "rename failed, copying instead");
+                    fs::copy(&frame.1, &target)
+                        .await
+                        .context("failed to copy frame")?;
+                    fs::remove_file(&frame.1)
+                        .await
+                        .context("failed to remove frame")?;
+                }
+            },
+            Disposer::Tar(slot) => {
+                let name = frame.1.file_name().context("frame has no file name")?.to_owned();
+                let mut builder = slot.take().context("frame archive is gone")?;
+                let path = frame.1.clone();
+                let (builder, appended) = tokio::task::spawn_blocking(move || {
+                    let appended = builder.append_path_with_name(&path, name);
+                    (builder, appended)
+                })
+                .await
+                .context("archive task failed")?;
+                *slot = Some(builder);
+                appended.context("failed to append frame to archive")?;
+                fs::remove_file(&frame.1)
+                    .await
+                    .context("failed to remove frame")?;
+            },
+        }
+
+        Ok(())
+    }
+
+    pub async fn finish(self) -> anyhow::Result<()> {
+        if let Disposer::Tar(Some(builder)) = self {
+            tokio::task::spawn_blocking(move || builder.into_inner())
+                .await
+                .context("archive task failed")?
+                .context("failed to finish frame archive")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_policies() {
+        assert_eq!("delete".parse::<FrameDisposal>().unwrap(), FrameDisposal::default());
+        assert_eq!(
+            "delete-no-error".parse::<FrameDisposal>().unwrap(),
+            FrameDisposal::Delete {
+                ignore_errors: true,
+            }
+        );
+        assert_eq!(" keep".parse::<FrameDisposal>().unwrap(), FrameDisposal::Keep);
+        assert_eq!(
+            "move:./done".parse::<FrameDisposal>().unwrap(),
+            FrameDisposal::Move(PathBuf::from("./done"))
+        );
+        assert_eq!(
+            "tar:C:/frames.tar".parse::<FrameDisposal>().unwrap(),
+            FrameDisposal::Tar(PathBuf::from("C:/frames.tar"))
+        );
+
+        assert!("move".parse::<FrameDisposal>().is_err());
+        assert!("tar".parse::<FrameDisposal>().is_err());
+        assert!("keep:./done".parse::<FrameDisposal>().is_err());
+        assert!("shred".parse::<FrameDisposal>().is_err());
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in ["delete", "delete-no-error", "keep", "move:done", "tar:frames.tar"] {
+            assert_eq!(s.parse::<FrameDisposal>().unwrap().to_string(), s);
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn dispose_frames() {
+        let dir = std::env::temp_dir().join(format!("vidgen-disposal-{}", std::process::id()));
+        let moved = dir.join("moved");
+        fs::create_dir_all(&dir).await.unwrap();
+        let frame = |fid: u64| Frame(fid, dir.join(format!("{}.png", fid)));
+        for fid in 0..3 {
+            fs::write(&frame(fid).1, b"frame").await.unwrap();
+        }
+
+        let mut keep = FrameDisposal::Keep.open().await.unwrap();
+        keep.dispose(&frame(0)).await.unwrap();
+        assert!(frame(0).1.exists());
+
+        let mut mover = FrameDisposal::Move(moved.clone()).open().await.unwrap();
+        mover.dispose(&frame(0)).await.unwrap();
+        assert!(!frame(0).1.exists());
+        assert!(moved.join("0.png").exists());
+
+        let mut delete = FrameDisposal::default().open().await.unwrap();
+        delete.dispose(&frame(1)).await.unwrap();
+        assert!(!frame(1).1.exists());
+        assert!(delete.dispose(&frame(1)).await.is_err());
+
+        let mut lenient = FrameDisposal::Delete {
+            ignore_errors: true,
+        }
+        .open()
+        .await
+        .unwrap();
+        lenient.dispose(&frame(1)).await.unwrap();
+
+        let archive = dir.join("frames.tar");
+        let mut tar = FrameDisposal::Tar(archive.clone()).open().await.unwrap();
+        tar.dispose(&frame(2)).await.unwrap();
+        tar.finish().await.unwrap();
+        assert!(!frame(2).1.exists());
+        let mut entries = tar::Archive::new(std::fs::File::open(&archive).unwrap());
+        let names: Vec<_> = entries
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().into_owned())
+            .collect();
+        assert_eq!(names, vec![PathBuf::from("2.png")]);
+
+        fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,421 @@
+use std::fmt;
+
+use clap::ArgEnum;
+
+use crate::{av1, color::PixelFormat, ffv1, mpeg4, prores, vpx, x264, x265};
+
+/// The video encoders vidgen knows how to drive.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum Codec {
+    X264,
+    X265,
+    Vp9,
+    SvtAv1,
+    AomAv1,
+    Prores,
+    Ffv1,
+    Mpeg4,
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Codec::X264 => "x264",
+            Codec::X265 => "x265",
+            Codec::Vp9 => "vp9",
+            Codec::SvtAv1 => "svt-av1",
+            Codec::AomAv1 => "aom-av1",
+            Codec::Prores => "prores",
+            Codec::Ffv1 => "ffv1",
+            Codec::Mpeg4 => "mpeg4",
+        };
+        f.write_str(s)
+    }
+}
+
+/// The encoder settings as given on the command line, not yet validated for a backend.
+#[derive(Debug, Default, Clone)]
+pub struct EncoderSettings {
+    pub preset:  Option<String>,
+    pub quality: Option<String>,
+    pub tune:    Option<String>,
+}
+
+/// A validated video encoder configuration.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VideoEncoder {
+    X264 {
+        preset: x264::X264Preset,
+        tune:   Option<x264::X264Tune>,
+        rate:   Option<x264::RateControl>,
+    },
+    X265 {
+        preset: x265::X265Preset,
+        tune:   Option<x265::X265Tune>,
+        crf:    Option<x265::Crf>,
+    },
+    Vp9 {
+        deadline: vpx::Vp9Deadline,
+        cpu_used: Option<vpx::CpuUsed>,
+        crf:      Option<vpx::Crf>,
+    },
+    SvtAv1 {
+        preset: Option<av1::SvtPreset>,
+        crf:    Option<av1::Crf>,
+    },
+    AomAv1 {
+        cpu_used: Option<av1::AomCpuUsed>,
+        crf:      Option<av1::Crf>,
+    },
+    Prores {
+        profile: prores::ProresProfile,
+        qscale:  Option<prores::QScale>,
+    },
+    Ffv1 {
+        level: ffv1::Ffv1Level,
+    },
+    Mpeg4 {
+        qscale: Option<mpeg4::QScale>,
+    },
+}
+
+pub fn parse_enum<T: ArgEnum>(what: &str, s: &str) -> anyhow::Result<T> {
+    T::from_str(s, true).map_err(|_| {
+        let valid = T::value_variants()
+            .iter()
+            .filter_map(|v| v.to_possible_value())
+            .map(|v| v.get_name())
+            .collect::<Vec<_>>()
+            .join(", ");
+        anyhow::anyhow!("unknown {} `{}`, valid values are: {}", what, s, valid)
+    })
+}
+
+fn reject(codec: Codec, what: &str, value: &Option<String>) -> anyhow::Result<()> {
+    if value.is_some() {
+        anyhow::bail!("the {} encoder does not support setting a {}", codec, what);
+    }
+    Ok(())
+}
+
+impl VideoEncoder {
+    /// Validate `settings` for the given codec. `x264` carries the dedicated x264 options
+    /// which are used when the generic ones are not given.
+    pub fn new(
+        codec: Codec,
+        settings: &EncoderSettings,
+        x264: (x264::X264Preset, Option<x264::X264Tune>, Option<x264::RateControl>),
+    ) -> anyhow::Result<Self> {
+        let EncoderSettings {
+            preset,
+            quality,
+            tune,
+        } = settings;
+
+        let enc = match codec {
+            Codec::X264 => VideoEncoder::X264 {
+                preset: match preset {
+                    Some(p) => parse_enum("x264 preset", p)?,
+                    None => x264.0,
+                },
+                tune:   match tune {
+                    Some(t) => Some(parse_enum("x264 tune", t)?),
+                    None => x264.1,
+                },
+                rate:   match quality {
+                    Some(q) => Some(x264::RateControl::Crf {
+                        crf: x264::Crf::parse(q)?,
+                        vbv: None,
+                    }),
+                    None => x264.2,
+                },
+            },
+            Codec::X265 => VideoEncoder::X265 {
+                preset: match preset {
+                    Some(p) => parse_enum("x265 preset", p)?,
+                    None => x265::X265Preset::Medium,
+                },
+                tune:   tune.as_deref().map(|t| parse_enum("x265 tune", t)).transpose()?,
+                crf:    quality.as_deref().map(x265::Crf::parse).transpose()?,
+            },
+            Codec::Vp9 => {
+                reject(codec, "tune", tune)?;
+                // the preset is either a deadline or a cpu-used value
+                let (deadline, cpu_used) = match preset {
+                    Some(p) if p.parse::<u8>().is_ok() => {
+                        (vpx::Vp9Deadline::Good, Some(vpx::CpuUsed::parse(p)?))
+                    },
+                    Some(p) => (parse_enum("vp9 deadline", p)?, None),
+                    None => (vpx::Vp9Deadline::Good, None),
+                };
+                VideoEncoder::Vp9 {
+                    deadline,
+                    cpu_used,
+                    crf: quality.as_deref().map(vpx::Crf::parse).transpose()?,
+                }
+            },
+            Codec::SvtAv1 => {
+                reject(codec, "tune", tune)?;
+                VideoEncoder::SvtAv1 {
+                    preset: preset.as_deref().map(av1::SvtPreset::parse).transpose()?,
+                    crf:    quality.as_deref().map(av1::Crf::parse).transpose()?,
+                }
+            },
+            Codec::AomAv1 => {
+                reject(codec, "tune", tune)?;
+                VideoEncoder::AomAv1 {
+                    cpu_used: preset.as_deref().map(av1::AomCpuUsed::parse).transpose()?,
+                    crf:      quality.as_deref().map(av1::Crf::parse).transpose()?,
+                }
+            },
+            Codec::Prores => {
+                reject(codec, "tune", tune)?;
+                VideoEncoder::Prores {
+                    profile: match preset {
+                        Some(p) => parse_enum("prores profile", p)?,
+                        None => prores::ProresProfile::Hq,
+                    },
+                    qscale:  quality.as_deref().map(prores::QScale::parse).transpose()?,
+                }
+            },
+            Codec::Ffv1 => {
+                reject(codec, "tune", tune)?;
+                reject(codec, "quality (it is lossless)", quality)?;
+                VideoEncoder::Ffv1 {
+                    level: match preset {
+                        Some(p) => parse_enum("ffv1 level", p)?,
+                        None => ffv1::Ffv1Level::V3,
+                    },
+                }
+            },
+            Codec::Mpeg4 => {
+                reject(codec, "tune", tune)?;
+                reject(codec, "preset", preset)?;
+                VideoEncoder::Mpeg4 {
+                    qscale: quality.as_deref().map(mpeg4::QScale::parse).transpose()?,
+                }
+            },
+        };
+
+        Ok(enc)
+    }
+
+    /// The ffmpeg encoder name, passed to `-c:v`.
+    pub fn codec_name(&self) -> &'static str {
+        match self {
+            VideoEncoder::X264 { .. } => "libx264",
+            VideoEncoder::X265 { .. } => "libx265",
+            VideoEncoder::Vp9 { .. } => "libvpx-vp9",
+            VideoEncoder::SvtAv1 { .. } => "libsvtav1",
+            VideoEncoder::AomAv1 { .. } => "libaom-av1",
+            VideoEncoder::Prores { .. } => "prores_ks",
+            VideoEncoder::Ffv1 { .. } => "ffv1",
+            VideoEncoder::Mpeg4 { .. } => "mpeg4",
+        }
+    }
+
+    /// The codec of the encoded stream, as named by ffprobe.
+    pub fn stream_codec(&self) -> &'static str {
+        match self {
+            VideoEncoder::X264 { .. } => "h264",
+            VideoEncoder::X265 { .. } => "hevc",
+            VideoEncoder::Vp9 { .. } => "vp9",
+            VideoEncoder::SvtAv1 { .. } | VideoEncoder::AomAv1 { .. } => "av1",
+            VideoEncoder::Prores { .. } => "prores",
+            VideoEncoder::Ffv1 { .. } => "ffv1",
+            VideoEncoder::Mpeg4 { .. } => "mpeg4",
+        }
+    }
+
+    pub fn pix_fmt(&self) -> &'static str {
+        match self {
+            VideoEncoder::Prores { profile, .. } => profile.pix_fmt(),
+            VideoEncoder::Ffv1 { .. } => "yuv444p",
+            _ => "yuv420p",
+        }
+    }
+
+    /// Whether the encoder can produce the given pixel format.
+    pub fn supports(&self, pix_fmt: PixelFormat) -> bool {
+        match self {
+            VideoEncoder::SvtAv1 { .. } => {
+                matches!(pix_fmt, PixelFormat::Yuv420p | PixelFormat::Yuv420p10le)
+            },
+            VideoEncoder::Mpeg4 { .. } => pix_fmt == PixelFormat::Yuv420p,
+            // the profile decides the pixel format
+            VideoEncoder::Prores { profile, .. } => profile.pix_fmt() == pix_fmt.to_string(),
+            _ => true,
+        }
+    }
+
+    /// Encoder specific ffmpeg options, without `-c:v` and `-pix_fmt`.
+    pub fn args(&self) -> Vec<String> {
+        let mut args: Vec<String> = Vec::new();
+        let mut push = |k: &str, v: String| {
+            args.push(k.to_owned());
+            args.push(v);
+        };
+
+        match self {
+            VideoEncoder::X264 { preset, tune, rate } => {
+                push("-preset:v", preset.to_string());
+                if let Some(tune) = tune {
+                    push("-tune", tune.to_string());
+                }
+                if let Some(rate) = rate {
+                    args.extend(rate.args());
+                }
+            },
+            VideoEncoder::X265 { preset, tune, crf } => {
+                push("-preset:v", preset.to_string());
+                if let Some(tune) = tune {
+                    push("-tune", tune.to_string());
+                }
+                if let Some(crf) = crf {
+                    push("-crf", crf.0.to_string());
+                }
+            },
+            VideoEncoder::Vp9 {
+                deadline,
+                cpu_used,
+                crf,
+            } => {
+                push("-deadline", deadline.to_string());
+                if let Some(cpu_used) = cpu_used {
+                    push("-cpu-used", cpu_used.0.to_string());
+                }
+                if let Some(crf) = crf {
+                    // constant quality mode needs the bitrate to be unconstrained
+                    push("-crf", crf.0.to_string());
+                    push("-b:v", String::from("0"));
+                }
+            },
+            VideoEncoder::SvtAv1 { preset, crf } => {
+                if let Some(preset) = preset {
+                    push("-preset:v", preset.0.to_string());
+                }
+                if let Some(crf) = crf {
+                    push("-crf", crf.0.to_string());
+                }
+            },
+            VideoEncoder::AomAv1 { cpu_used, crf } => {
+                if let Some(cpu_used) = cpu_used {
+                    push("-cpu-used", cpu_used.0.to_string());
+                }
+                if let Some(crf) = crf {
+                    push("-crf", crf.0.to_string());
+                    push("-b:v", String::from("0"));
+                }
+            },
+            VideoEncoder::Prores { profile, qscale } => {
+                push("-profile:v", profile.to_string());
+                if let Some(qscale) = qscale {
+                    push("-qscale:v", qscale.0.to_string());
+                }
+            },
+            VideoEncoder::Ffv1 { level } => {
+                push("-level", level.to_string());
+            },
+            VideoEncoder::Mpeg4 { qscale } => {
+                if let Some(qscale) = qscale {
+                    push("-qscale:v", qscale.0.to_string());
+                }
+            },
+        }
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const X264: (x264::X264Preset, Option<x264::X264Tune>, Option<x264::RateControl>) =
+        (x264::X264Preset::Slow, Some(x264::X264Tune::Film), None);
+
+    fn settings(
+        preset: Option<&str>,
+        quality: Option<&str>,
+        tune: Option<&str>,
+    ) -> EncoderSettings {
+        EncoderSettings {
+            preset:  preset.map(String::from),
+            quality: quality.map(String::from),
+            tune:    tune.map(String::from),
+        }
+    }
+
+    #[test]
+    fn x264_generic_options_win() {
+        let enc = VideoEncoder::new(Codec::X264, &settings(None, None, None), X264).unwrap();
+        assert_eq!(enc, VideoEncoder::X264 {
+            preset: x264::X264Preset::Slow,
+            tune:   Some(x264::X264Tune::Film),
+            rate:   None,
+        });
+
+        let given = settings(Some("fast"), Some("20"), Some("animation"));
+        let enc = VideoEncoder::new(Codec::X264, &given, X264).unwrap();
+        assert_eq!(enc, VideoEncoder::X264 {
+            preset: x264::X264Preset::Fast,
+            tune:   Some(x264::X264Tune::Animation),
+            rate:   Some(x264::RateControl::Crf {
+                crf: x264::Crf(20),
+                vbv: None,
+            }),
+        });
+    }
+
+    #[test]
+    fn vp9_preset() {
+        let enc = VideoEncoder::new(Codec::Vp9, &settings(Some("4"), None, None), X264).unwrap();
+        assert_eq!(enc, VideoEncoder::Vp9 {
+            deadline: vpx::Vp9Deadline::Good,
+            cpu_used: Some(vpx::CpuUsed(4)),
+            crf:      None,
+        });
+
+        let enc =
+            VideoEncoder::new(Codec::Vp9, &settings(Some("realtime"), Some("63"), None), X264)
+                .unwrap();
+        assert_eq!(enc, VideoEncoder::Vp9 {
+            deadline: vpx::Vp9Deadline::Realtime,
+            cpu_used: None,
+            crf:      Some(vpx::Crf(63)),
+        });
+
+        assert!(VideoEncoder::new(Codec::Vp9, &settings(Some("9"), None, None), X264).is_err());
+        assert!(VideoEncoder::new(Codec::Vp9, &settings(None, Some("64"), None), X264).is_err());
+    }
+
+    #[test]
+    fn unsupported_options() {
+        let tuned = settings(None, None, Some("film"));
+        assert!(VideoEncoder::new(Codec::Vp9, &tuned, X264).is_err());
+        assert!(VideoEncoder::new(Codec::Prores, &tuned, X264).is_err());
+
+        assert!(VideoEncoder::new(Codec::Ffv1, &settings(None, Some("1"), None), X264).is_err());
+        assert!(VideoEncoder::new(Codec::Mpeg4, &settings(Some("1"), None, None), X264).is_err());
+        let unknown = settings(Some("fastest"), None, None);
+        assert!(VideoEncoder::new(Codec::X264, &unknown, X264).is_err());
+    }
+
+    #[test]
+    fn pixel_formats() {
+        let svt = VideoEncoder::new(Codec::SvtAv1, &settings(None, None, None), X264).unwrap();
+        assert!(svt.supports(PixelFormat::Yuv420p10le));
+        assert!(!svt.supports(PixelFormat::Yuv444p));
+
+        let mpeg4 = VideoEncoder::new(Codec::Mpeg4, &settings(None, None, None), X264).unwrap();
+        assert!(mpeg4.supports(PixelFormat::Yuv420p));
+        assert!(!mpeg4.supports(PixelFormat::Yuv420p10le));
+
+        let hq = VideoEncoder::new(Codec::Prores, &settings(None, None, None), X264).unwrap();
+        assert!(hq.supports(PixelFormat::Yuv422p10le));
+        assert!(!hq.supports(PixelFormat::Yuv444p10le));
+        let p4444 =
+            VideoEncoder::new(Codec::Prores, &settings(Some("4444"), None, None), X264).unwrap();
+        assert!(p4444.supports(PixelFormat::Yuv444p10le));
+    }
+}
@@ -40,6 +40,96 @@ impl Ffmpeg {
     pub fn ffprobe(&self) -> PathBuf { self.path_for_name(ffmpeg_names::FFPROBE) }
 
     pub fn ffmpeg(&self) -> PathBuf { self.path_for_name(ffmpeg_names::FFMPEG) }
+
+    /// Length of a media file in seconds.
+    pub async fn probe_duration(&self, file: &Path) -> anyhow::Result<f64> {
+        let output = Command::new(self.ffprobe())
+            .args([
+                "-v",
+                "error",
+                "-show_entries",
+                "format=duration",
+                "-of",
+                "default=noprint_wrappers=1:nokey=1",
+            ])
+            .arg(file)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+            .await
+            .context("failed to spawn ffprobe")?;
+        if !output.status.success() {
+            anyhow::bail!("ffprobe exited with {}", output.status);
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .trim()
+            .parse()
+            .context("ffprobe did not report a duration")
+    }
+
+    /// Codec name of the first audio stream of a media file.
+    pub async fn probe_audio_codec(&self, file: &Path) -> anyhow::Result<String> {
+        let output = Command::new(self.ffprobe())
+            .args([
+                "-v",
+                "error",
+                "-select_streams",
+                "a:0",
+                "-show_entries",
+                "stream=codec_name",
+                "-of",
+                "default=noprint_wrappers=1:nokey=1",
+            ])
+            .arg(file)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .output()
+            .await
+            .context("failed to spawn ffprobe")?;
+        if !output.status.success() {
+            anyhow::bail!("ffprobe exited with {}", output.status);
+        }
+
+        let codec = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        if codec.is_empty() {
+            anyhow::bail!("no audio stream found");
+        }
+        Ok(codec)
+    }
+
+    /// Render a frame filled with `color` in the same size and format as `reference`.
+    pub async fn render_placeholder(&self, reference: &Path, color: &str) -> anyhow::Result<Vec<u8>> {
+        let ext = reference
+            .extension()
+            .context("reference frame has no extension")?;
+        let target = std::env::temp_dir()
+            .join(format!("vidgen-placeholder-{}", std::process::id()))
+            .with_extension(ext);
+
+        let status = Command::new(self.ffmpeg())
+            .args(["-v", "error", "-y", "-i"])
+            .arg(reference)
+            .arg("-vf")
+            .arg(format!("drawbox=x=0:y=0:w=iw:h=ih:color={}:t=fill", color))
+            .args(["-frames:v", "1"])
+            .arg(&target)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .context("failed to spawn ffmpeg")?;
+        if !status.success() {
+            anyhow::bail!("ffmpeg exited with {} while rendering the placeholder", status);
+        }
+
+        let data = tokio::fs::read(&target)
+            .await
+            .context("failed to read placeholder frame")?;
+        let _ = tokio::fs::remove_file(&target).await;
+
+        Ok(data)
+    }
 }
 
 pub async fn ensure_ffmpeg_dir(dir: Option<String>, need_ffprobe: bool) -> anyhow::Result<Ffmpeg> {
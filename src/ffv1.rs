@@ -0,0 +1,20 @@
+use std::fmt;
+
+/// FFV1 bitstream version, only 1 and 3 are considered stable.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd, clap::ArgEnum)]
+pub enum Ffv1Level {
+    #[clap(name = "1")]
+    V1,
+    #[clap(name = "3")]
+    V3,
+}
+
+impl fmt::Display for Ffv1Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Ffv1Level::V1 => "1",
+            Ffv1Level::V3 => "3",
+        };
+        f.write_str(s)
+    }
+}
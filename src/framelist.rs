@@ -1,54 +1,373 @@
 use anyhow::Context;
 use futures::StreamExt;
-use once_cell::sync::Lazy;
 use regex::Regex;
-use std::{cmp::Ordering, path::PathBuf};
+use std::{cmp::Ordering, fmt, path::PathBuf, str::FromStr};
 use tokio_stream::wrappers::ReadDirStream;
 
-pub static NAME_REGEX: Lazy<Regex> =
-    Lazy::new(|| Regex::new(r"(\d+)\.\w{3,4}").expect("compiled regex is invalid"));
+/// Name of the capture group holding the frame number.
+const FRAME_GROUP: &str = "frame";
 
-#[derive(Debug, Eq, PartialEq)]
+pub const DEFAULT_PATTERN: &str = r"(?P<frame>\d+)\.\w{3,4}";
+
+/// Matches frame file names and extracts their frame number.
+///
+/// Either a regex containing a `(?P<frame>...)` group, or a template where the frame
+/// number is given as printf style (`frame_%05d.png`) or hashes (`img_####.png`).
+#[derive(Debug, Clone)]
+pub struct FramePattern(Regex);
+
+impl FramePattern {
+    pub fn frame_number(&self, name: &str) -> Option<u64> {
+        self.0.captures(name)?.name(FRAME_GROUP)?.as_str().parse().ok()
+    }
+
+    fn from_template(template: &str) -> anyhow::Result<Self> {
+        let mut regex = String::from("^");
+        let mut literal = String::new();
+        let mut numbers = 0;
+
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            let width = match c {
+                '%' if chars.peek() == Some(&'%') => {
+                    chars.next();
+                    literal.push('%');
+                    continue;
+                },
+                '%' => {
+                    let mut spec = String::new();
+                    while let Some(d) = chars.next_if(char::is_ascii_digit) {
+                        spec.push(d);
+                    }
+                    if chars.next() != Some('d') {
+                        anyhow::bail!("only `%d` style placeholders are supported in templates");
+                    }
+                    spec.parse::<usize>().unwrap_or(1).max(1)
+                },
+                '#' => {
+                    let mut width = 1;
+                    while chars.next_if_eq(&'#').is_some() {
+                        width += 1;
+                    }
+                    width
+                },
+                _ => {
+                    literal.push(c);
+                    continue;
+                },
+            };
+
+            regex.push_str(&regex::escape(&literal));
+            literal.clear();
+            regex.push_str(&format!("(?P<{}>\\d{{{},}})", FRAME_GROUP, width));
+            numbers += 1;
+        }
+        regex.push_str(&regex::escape(&literal));
+        regex.push('$');
+
+        match numbers {
+            1 => Ok(FramePattern(
+                Regex::new(&regex).context("template did not produce a valid regex")?,
+            )),
+            0 => anyhow::bail!("template has no frame number placeholder (`%05d` or `####`)"),
+            _ => anyhow::bail!("template has more than one frame number placeholder"),
+        }
+    }
+}
+
+impl FromStr for FramePattern {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(&format!("(?P<{}>", FRAME_GROUP)) {
+            Regex::new(s)
+                .map(FramePattern)
+                .context("the pattern is not a valid regex")
+        } else {
+            Self::from_template(s)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub struct Frame(pub u64, pub PathBuf);
 
 impl PartialOrd for Frame {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.0.partial_cmp(&other.0)
-    }
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> { Some(self.cmp(other)) }
 }
 
 impl Ord for Frame {
-    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.partial_cmp(other).unwrap_or(Ordering::Less)
-    }
+    fn cmp(&self, other: &Self) -> Ordering { self.0.cmp(&other.0) }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FrameList {
     pub frames: Vec<Frame>,
+    /// Expected distance between two consecutive frame numbers
+    pub step:   u64,
+}
+
+/// A run of missing frame numbers, `start..=end`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Gap {
+    pub start: u64,
+    pub end:   u64,
+    pub step:  u64,
+}
+
+impl Gap {
+    /// The gap between two consecutive frames, if there is one.
+    pub fn between(prev: u64, next: u64, step: u64) -> Option<Gap> {
+        if next > prev + step {
+            Some(Gap {
+                start: prev + step,
+                end: next - step,
+                step,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn count(&self) -> u64 { (self.end - self.start) / self.step + 1 }
+}
+
+impl fmt::Display for Gap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}", self.start)
+        } else {
+            write!(f, "{}-{}", self.start, self.end)
+        }
+    }
+}
+
+/// Format a list of gaps for humans, only listing the first few.
+pub fn gap_report(gaps: &[Gap]) -> String {
+    const SHOWN: usize = 10;
+
+    let missing: u64 = gaps.iter().map(Gap::count).sum();
+    let mut report = gaps
+        .iter()
+        .take(SHOWN)
+        .map(|g| g.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if gaps.len() > SHOWN {
+        report.push_str(&format!(" and {} more", gaps.len() - SHOWN));
+    }
+
+    format!("{} missing frames: {}", missing, report)
+}
+
+/// Which part of a frame sequence to encode.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct FrameRange {
+    pub start: Option<u64>,
+    pub end:   Option<u64>,
+    pub step:  u64,
+    pub max:   Option<u64>,
+}
+
+impl FrameRange {
+    /// Whether the frame falls into the range, ignoring `max`.
+    pub fn contains(&self, fid: u64) -> bool {
+        let start = self.start.unwrap_or(0);
+        fid >= start
+            && self.end.map(|end| fid <= end).unwrap_or(true)
+            && (fid - start) % self.step == 0
+    }
+}
+
+/// How to treat holes in the frame numbering.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum GapPolicy {
+    /// Refuse to encode
+    Error,
+    /// Log the gaps and encode the frames as they are
+    Warn,
+    /// Repeat the previous frame for the missing slots
+    Hold,
+    /// Insert a solid frame of the given ffmpeg color for the missing slots
+    Placeholder(String),
+}
+
+impl fmt::Display for GapPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GapPolicy::Error => f.write_str("error"),
+            GapPolicy::Warn => f.write_str("warn"),
+            GapPolicy::Hold => f.write_str("hold"),
+            GapPolicy::Placeholder(color) => write!(f, "placeholder:{}", color),
+        }
+    }
+}
+
+impl FromStr for GapPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, ':');
+
+        // the unwrap here will always succeed as splitn returns at least 1 element
+        match (split.next().unwrap().trim(), split.next()) {
+            ("error", None) => Ok(GapPolicy::Error),
+            ("warn", None) => Ok(GapPolicy::Warn),
+            ("hold", None) => Ok(GapPolicy::Hold),
+            ("placeholder", None) => Ok(GapPolicy::Placeholder(String::from("black"))),
+            ("placeholder", Some(color)) => Ok(GapPolicy::Placeholder(color.trim().to_owned())),
+            _ => anyhow::bail!(
+                "unknown gap policy, valid options are: error, warn, hold, placeholder[:COLOR]"
+            ),
+        }
+    }
 }
 
 impl FrameList {
-    pub async fn from_dir(dir: &str) -> anyhow::Result<Self> {
+    pub async fn from_dir(dir: &str, pattern: &FramePattern) -> anyhow::Result<Self> {
         let mut frames: Vec<Frame> = ReadDirStream::new(
             tokio::fs::read_dir(dir)
                 .await
                 .context("failed to list files in source directory")?,
         )
         .filter_map(|v| async { v.ok() })
-        .filter_map(Self::filter_item)
+        .filter_map(|e| Self::filter_item(e, pattern))
         .collect()
         .await;
 
         frames.sort();
 
-        Ok(FrameList { frames })
+        Ok(FrameList { frames, step: 1 })
     }
 
-    pub async fn filter_item(entry: tokio::fs::DirEntry) -> Option<Frame> {
-        let fname = entry.file_name().to_str()?.to_owned();
-        let m = NAME_REGEX.captures(&fname)?;
-        let fid = m.get(1)?.as_str().parse().ok()?;
+    /// Drop every frame outside of `range`. If the range has no start it is set to the first
+    /// frame, so that later lookups use the same base for the step.
+    pub fn select(&mut self, range: &mut FrameRange) {
+        if range.start.is_none() {
+            range.start = self.frames.first().map(|f| f.0);
+        }
+
+        self.frames.retain(|f| range.contains(f.0));
+        if let Some(max) = range.max {
+            self.frames.truncate(max as usize);
+        }
+        self.step = range.step;
+    }
+
+    /// Find all holes in the (sorted) frame numbering.
+    pub fn gaps(&self) -> Vec<Gap> {
+        self.frames
+            .windows(2)
+            .filter_map(|w| Gap::between(w[0].0, w[1].0, self.step))
+            .collect()
+    }
+
+    pub async fn filter_item(entry: tokio::fs::DirEntry, pattern: &FramePattern) -> Option<Frame> {
+        let fid = pattern.frame_number(entry.file_name().to_str()?)?;
         Some(Frame(fid, entry.path()))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template_printf() {
+        let pattern: FramePattern = "frame_%05d.png".parse().unwrap();
+        assert_eq!(pattern.frame_number("frame_00042.png"), Some(42));
+        assert_eq!(pattern.frame_number("frame_123456.png"), Some(123456));
+        assert_eq!(pattern.frame_number("frame_0042.png"), None);
+        assert_eq!(pattern.frame_number("frame_00042.jpg"), None);
+        assert_eq!(pattern.frame_number("xframe_00042.png"), None);
+    }
+
+    #[test]
+    fn template_hashes() {
+        let pattern: FramePattern = "img_####.png".parse().unwrap();
+        assert_eq!(pattern.frame_number("img_0007.png"), Some(7));
+        assert_eq!(pattern.frame_number("img_007.png"), None);
+    }
+
+    #[test]
+    fn template_escapes() {
+        let pattern: FramePattern = "100%%_%d.(a).png".parse().unwrap();
+        assert_eq!(pattern.frame_number("100%_5.(a).png"), Some(5));
+        assert_eq!(pattern.frame_number("100%_5.(a)xpng"), None);
+
+        let pattern: FramePattern = "f%0d.png".parse().unwrap();
+        assert_eq!(pattern.frame_number("f3.png"), Some(3));
+    }
+
+    #[test]
+    fn template_placeholders() {
+        assert!("frame.png".parse::<FramePattern>().is_err());
+        assert!("%d_%d.png".parse::<FramePattern>().is_err());
+        assert!("##_%d.png".parse::<FramePattern>().is_err());
+        assert!("frame_%s.png".parse::<FramePattern>().is_err());
+    }
+
+    #[test]
+    fn regex_pattern() {
+        let pattern: FramePattern = DEFAULT_PATTERN.parse().unwrap();
+        assert_eq!(pattern.frame_number("12.png"), Some(12));
+        assert_eq!(pattern.frame_number("12.tiff"), Some(12));
+        assert_eq!(pattern.frame_number("12.gz"), None);
+
+        assert!("(?P<frame>\\d+".parse::<FramePattern>().is_err());
+    }
+
+    fn frames(ids: &[u64]) -> FrameList {
+        FrameList {
+            frames: ids.iter().map(|&fid| Frame(fid, PathBuf::new())).collect(),
+            step:   1,
+        }
+    }
+
+    fn ids(list: &FrameList) -> Vec<u64> { list.frames.iter().map(|f| f.0).collect() }
+
+    #[test]
+    fn range_contains() {
+        let range = FrameRange {
+            start: Some(10),
+            end:   Some(20),
+            step:  5,
+            max:   None,
+        };
+        assert!(!range.contains(5));
+        assert!(range.contains(10));
+        assert!(!range.contains(11));
+        assert!(range.contains(15));
+        assert!(range.contains(20));
+        assert!(!range.contains(25));
+    }
+
+    #[test]
+    fn select_from_first_frame() {
+        let mut list = frames(&[3, 4, 5, 6, 7, 8, 9]);
+        let mut range = FrameRange {
+            start: None,
+            end:   None,
+            step:  3,
+            max:   Some(2),
+        };
+        list.select(&mut range);
+
+        assert_eq!(range.start, Some(3));
+        assert_eq!(ids(&list), vec![3, 6]);
+        assert_eq!(list.step, 3);
+        assert!(list.gaps().is_empty());
+    }
+
+    #[test]
+    fn gaps_with_step() {
+        let mut list = frames(&[0, 2, 8, 10]);
+        list.step = 2;
+        assert_eq!(list.gaps(), vec![Gap {
+            start: 4,
+            end:   6,
+            step:  2,
+        }]);
+        assert_eq!(list.gaps()[0].count(), 2);
+    }
+}
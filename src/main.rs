@@ -1,21 +1,51 @@
 #[macro_use]
 extern crate tracing;
 
-use crate::{framelist::FrameList, runner::Message};
+use crate::{
+    framelist::{Frame, FrameList},
+    runner::Message,
+};
 use anyhow::Context;
-use clap::Parser;
+use clap::{CommandFactory, FromArgMatches};
 use futures::StreamExt;
-use std::{fmt, fs::File, path::PathBuf, process::Stdio, str::FromStr};
+use std::{
+    fmt,
+    fs::File,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::atomic::Ordering,
+};
 use tokio::process::Command;
 use tokio_stream::wrappers::ReadDirStream;
 use tracing::metadata::LevelFilter;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
+mod animation;
+mod audio;
+mod av1;
+mod bitrate;
+mod color;
+mod config;
+mod container;
+mod dimensions;
+mod disposal;
+mod encoder;
 mod ffmpeg;
+mod ffv1;
 mod framelist;
+mod mpeg4;
+mod prores;
+mod progress;
 mod quirks;
+mod rendition;
 mod runner;
+mod scale;
+mod segments;
+mod thumbnails;
+mod transparency;
+mod vpx;
 mod x264;
+mod x265;
 
 macro_rules! ffarg {
     ($c:ident, $arg:expr) => {{
@@ -27,7 +57,23 @@ macro_rules! ffarg {
 }
 
 fn main() {
-    let args = Args::parse();
+    let matches = match config::load(Args::command()) {
+        Ok(matches) => matches,
+        Err(why) => {
+            eprintln!("ERROR!: Unable to load configuration: {:?}", why);
+            std::process::exit(2);
+        },
+    };
+    let args = match Args::from_arg_matches(&matches) {
+        Ok(args) => args,
+        Err(why) => why.exit(),
+    };
+
+    if args.print_config {
+        print!("{}", config::render(&Args::command(), &matches));
+        return;
+    }
+
     let wait = args.wait;
 
     let console_level = match args.debug {
@@ -111,29 +157,134 @@ const DIM_AUTO: &str = "auto";
 async fn program(args: Args) -> anyhow::Result<()> {
     info!("startup");
 
-    for line in args.extra_info {
+    for line in &args.extra_info {
         info!(data=?line, "extra info");
     }
 
-    let ffmpeg = ffmpeg::ensure_ffmpeg_dir(args.ffmpeg.clone(), args.input_dim == DIM_AUTO)
+    let audio_copy = args.audio_codec == Some(audio::AudioCodec::Copy) && !args.audio.is_empty();
+    let need_ffprobe = args.input_dim == DIM_AUTO
+        || audio_copy
+        || args.audio.iter().any(audio::AudioTrack::needs_duration);
+    let ffmpeg = ffmpeg::ensure_ffmpeg_dir(args.ffmpeg.clone(), need_ffprobe)
         .await
         .context("ffmpeg discovery failed")?;
 
+    let mut audio_tracks = args.audio.clone();
+    for track in audio_tracks.iter_mut().filter(|t| t.needs_duration()) {
+        let duration = ffmpeg
+            .probe_duration(&track.file)
+            .await
+            .with_context(|| format!("failed to probe the length of {}", track.file.display()))?;
+        track.duration = Some(duration);
+    }
+
+    // animations pick their own muxer
+    let container = match args.animation {
+        Some(animation) => {
+            if !animation.matches_target(Path::new(&args.target)) {
+                warn!(
+                    target=%args.target,
+                    %animation,
+                    "target extension does not match the animation format"
+                );
+            }
+            None
+        },
+        None => args
+            .container
+            .or(args.alpha.map(|alpha| alpha.container()))
+            .or_else(|| container::Container::from_target(Path::new(&args.target))),
+    };
+    if let Some(container) = container {
+        if container::Container::from_target(Path::new(&args.target)) != Some(container) {
+            warn!(target=%args.target, %container, "target extension does not match the container");
+        }
+    }
+    let audio_settings = audio::AudioSettings {
+        codec:       args.audio_codec.unwrap_or(match container {
+            Some(container::Container::Webm) => audio::AudioCodec::Opus,
+            _ => audio::AudioCodec::Aac,
+        }),
+        sample_rate: args.audio_sample_rate,
+        channels:    args.audio_channels,
+        quality:     args.audio_quality.clone(),
+    };
+    let mut copied = Vec::new();
+    if audio_copy {
+        for track in &audio_tracks {
+            copied.push(
+                ffmpeg
+                    .probe_audio_codec(&track.file)
+                    .await
+                    .with_context(|| format!("failed to probe {}", track.file.display()))?,
+            );
+        }
+    }
+    audio_settings
+        .validate(&audio_tracks, container, &copied)
+        .context("invalid audio settings")?;
+
+    // copied tracks are mapped directly, they can not pass through a filter
+    let audio_graph = if audio_copy {
+        String::new()
+    } else {
+        audio_tracks
+            .iter()
+            .enumerate()
+            .map(|(idx, track)| {
+                let outputs = args.rendition.len() + 1;
+                if outputs == 1 {
+                    return track.filter(idx + 1, &format!("a{}", idx));
+                }
+                // every output needs its own copy of the track, renditions get `[aNrM]`
+                let copies: String = (1..outputs)
+                    .map(|out| format!("[a{}r{}]", idx, out))
+                    .collect();
+                Ok(format!(
+                    "{};[t{idx}]asplit={}[a{idx}]{}",
+                    track.filter(idx + 1, &format!("t{}", idx))?,
+                    outputs,
+                    copies,
+                    idx = idx
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()
+            .context("invalid audio track")?
+            .join(";")
+    };
+
+    // a resumed segmented encode continues with the frames and frame size of its first run
+    let journal = if args.segment_frames.is_some() || args.jobs.get() > 1 {
+        let dir = segments::segment_dir(Path::new(&args.source));
+        let journal = segments::Journal::load(&dir)
+            .await
+            .context("failed to load segment journal")?;
+        Some(journal)
+    } else {
+        None
+    };
+    let resumed = journal.as_ref().filter(|j| !j.segments.is_empty());
+
     info!("reading source frame info");
-    let (frame_width, frame_height): (u32, u32) = match args.input_dim.as_str() {
-        v if v == DIM_AUTO => {
+    let recorded_input = resumed.and_then(|j| j.input);
+    let (frame_width, frame_height): (u32, u32) = match (args.input_dim.as_str(), recorded_input) {
+        (v, Some(size)) if v == DIM_AUTO => {
+            info!(?size, "using the frame size of the resumed encode");
+            size
+        },
+        (v, None) if v == DIM_AUTO => {
             let span = warn_span!("frame-ident");
             let _guard = span.enter();
             info!("source frame size not set, identifying");
 
-            let ident_frame = find_ident_frame(&args.source)
+            let ident_frame = find_ident_frame(&args.source, &args.pattern)
                 .await
                 .context("failed to find ident frame")?;
 
             info!(ident_frame=%ident_frame);
 
             let data = Command::new(ffmpeg.ffprobe())
-                .args(&[
+                .args([
                     "-v",
                     "quiet",
                     "-select_streams",
@@ -157,67 +308,690 @@ async fn program(args: Args) -> anyhow::Result<()> {
                 .context("failed to parse stream info from ffprobe")?;
             let res = (fdt.streams[0].width, fdt.streams[0].height);
             info!(size=?res);
+            if res.0 == 0 || res.1 == 0 {
+                anyhow::bail!("the frame {} has no size", ident_frame);
+            }
             res
         },
-        exact => parse_resolution(exact).context("failed to parse input resolution")?,
+        (exact, _) => {
+            scale::parse_resolution(exact).context("failed to parse input resolution")?
+        },
     };
 
     let (target_width, target_height) =
-        parse_resolution(&args.output_dim).context("failed to parse output resolution")?;
+        scale::parse_resolution(&args.output_dim).context("failed to parse output resolution")?;
 
     info!(target_size=?(target_width, target_height));
 
-    let frames = FrameList::from_dir(&args.source)
+    let encoder_settings = encoder::EncoderSettings {
+        preset:  args.preset.clone(),
+        quality: args.quality.clone(),
+        tune:    args.tune.clone(),
+    };
+    let x264_settings = (
+        args.x264_preset.unwrap_or(x264::X264Preset::Medium),
+        args.x264_tune,
+        args.rate_control.or(args.crf.map(|crf| x264::RateControl::Crf { crf, vbv: None })),
+    );
+    let video_encoder = encoder::VideoEncoder::new(args.encoder, &encoder_settings, x264_settings)
+        .with_context(|| format!("invalid options for the {} encoder", args.encoder))?;
+    if args.rate_control.is_some() && args.encoder != encoder::Codec::X264 {
+        anyhow::bail!("rate control modes are only supported with the x264 encoder");
+    }
+    if args.encoder != encoder::Codec::X264
+        && (args.crf.is_some() || args.x264_preset.is_some() || args.x264_tune.is_some())
+    {
+        anyhow::bail!(
+            "--crf, --x264-preset and --x264-tune are only supported with the x264 encoder, use \
+             --quality, --preset and --tune instead"
+        );
+    }
+    if let Some(pix_fmt) = args.pix_fmt {
+        if !video_encoder.supports(pix_fmt) {
+            anyhow::bail!(
+                "the {} encoder does not support the {} pixel format",
+                args.encoder,
+                pix_fmt
+            );
+        }
+    }
+    if let Some(container) = container {
+        let codec = match args.alpha {
+            Some(alpha) => alpha.stream_codec(),
+            None => video_encoder.stream_codec(),
+        };
+        if !container.supports_video(codec) {
+            anyhow::bail!("{} video can not be stored in a {} container", codec, container);
+        }
+    }
+
+    if args.animation.is_some() && args.jobs.get() > 1 {
+        anyhow::bail!("animations can not be encoded in parallel jobs");
+    }
+    if !args.rendition.is_empty() && args.jobs.get() > 1 {
+        anyhow::bail!("renditions can not be combined with parallel jobs");
+    }
+    let mut renditions = Vec::new();
+    for rendition in &args.rendition {
+        let target = rendition.target.display();
+        if rendition.size.is_some() && args.scale_mode == scale::ScaleMode::None {
+            anyhow::bail!(
+                "the rendition {} has its own size, which needs a --scale-mode other than none",
+                target
+            );
+        }
+        let video_encoder = rendition
+            .video_encoder(args.encoder, &encoder_settings, x264_settings)
+            .with_context(|| format!("invalid encoder options for the rendition {}", target))?;
+        if let Some(pix_fmt) = args.pix_fmt {
+            if !video_encoder.supports(pix_fmt) {
+                anyhow::bail!(
+                    "the rendition {} does not support the {} pixel format",
+                    target,
+                    pix_fmt
+                );
+            }
+        }
+        let container = rendition.container();
+        if let Some(container) = container {
+            let codec = video_encoder.stream_codec();
+            if !container.supports_video(codec) {
+                anyhow::bail!(
+                    "{} video of the rendition {} can not be stored in a {} container",
+                    codec,
+                    target,
+                    container
+                );
+            }
+        }
+        audio_settings
+            .validate(&audio_tracks, container, &copied)
+            .with_context(|| format!("invalid audio settings for the rendition {}", target))?;
+
+        renditions.push(RenditionOutput {
+            target: rendition.target.clone(),
+            output: rendition.size.unwrap_or((target_width, target_height)),
+            video_encoder,
+            container,
+        });
+    }
+
+    let mut frames = FrameList::from_dir(&args.source, &args.pattern)
         .await
         .context("failed to index frames")?;
 
+    let mut range = framelist::FrameRange {
+        start: args.start_frame,
+        end:   args.end_frame,
+        step:  args.step.get(),
+        max:   args.max_frames,
+    };
+    if let Some(journal) = resumed {
+        range = journal
+            .resume(range)
+            .context("unable to resume the segmented encode")?;
+    }
+    frames.select(&mut range);
+    info!(?range, "selected frames");
+
     info!(frame_count=%frames.frames.len());
 
-    let mut com = Command::new(ffmpeg.ffmpeg());
-    ffarg!(com, "-y");
-    ffarg!(com, "-framerate", args.fps.to_string());
-    ffarg!(com, "-s", format!("{frame_width}x{frame_height}"));
-    ffarg!(com, "-an");
-    ffarg!(com, "-f", "image2pipe");
-    ffarg!(com, "-i", "-");
-    if let Some(audio) = args.audio {
-        info!(?audio.file, %audio.start, "requested audio, adding ffmpeg options");
-        ffarg!(com, "-i", audio.file);
-        ffarg!(
-            com,
-            "-filter_complex",
-            format!(
-                "[1:0]adelay={off}:all=1[ad];[ad]apad[a]",
-                off = (audio.start * 1000.0).trunc() as u64
+    let gaps = frames.gaps();
+    if !gaps.is_empty() {
+        let report = framelist::gap_report(&gaps);
+        match args.gaps {
+            framelist::GapPolicy::Error => {
+                error!(%report, "gaps in frame numbering");
+                anyhow::bail!("gaps in frame numbering, {}", report);
+            },
+            _ => warn!(%report, policy=%args.gaps, "gaps in frame numbering"),
+        }
+    }
+
+    let input_size = (frame_width, frame_height);
+    if args.size_mismatch != dimensions::SizeMismatch::Ignore {
+        info!("checking frame sizes");
+        let mismatches = dimensions::scan(&frames.frames, input_size)
+            .await
+            .context("failed to check frame sizes")?;
+        if !mismatches.is_empty() {
+            let report = dimensions::report(&mismatches, input_size);
+            match args.size_mismatch {
+                dimensions::SizeMismatch::Error => {
+                    error!(%report, "frame size mismatch");
+                    anyhow::bail!("frame size mismatch, {}", report);
+                },
+                _ => warn!(%report, "frame size mismatch, normalizing"),
+            }
+        }
+    }
+    // frames picked up later are only known to the runner, it normalizes every frame and checks
+    // the followed ones
+    let normalize =
+        (args.size_mismatch == dimensions::SizeMismatch::Normalize).then_some(input_size);
+
+    let source_path = PathBuf::from(&args.source);
+
+    let disposal = match args.frame_disposal.clone() {
+        disposal::FrameDisposal::Delete { ignore_errors } => disposal::FrameDisposal::Delete {
+            ignore_errors: ignore_errors
+                || args.keysight.map(|v| v.delete_no_error).unwrap_or(false),
+        },
+        other => {
+            if args.keysight.map(|v| v.delete_no_error).unwrap_or(false) {
+                warn!(disposal=%other, "the delete-no-error quirk only applies to deleted frames");
+            }
+            other
+        },
+    };
+    info!(%disposal, "frame disposal");
+    let disposer = disposal
+        .open()
+        .await
+        .context("failed to prepare frame disposal")?;
+
+    let follow = if args.follow {
+        Some(runner::FollowOptions {
+            sentinel:      source_path.join(&args.follow_sentinel),
+            source:        source_path.display().to_string(),
+            idle_timeout:  std::time::Duration::from_secs(args.follow_timeout),
+            poll_interval: std::time::Duration::from_millis(500),
+            pattern:       args.pattern.clone(),
+            range,
+            check_size:    (args.size_mismatch == dimensions::SizeMismatch::Error)
+                .then_some(input_size),
+        })
+    } else {
+        None
+    };
+
+    let gap_fill = match &args.gaps {
+        framelist::GapPolicy::Error => runner::GapFill::Error,
+        framelist::GapPolicy::Warn => runner::GapFill::Warn,
+        framelist::GapPolicy::Hold => runner::GapFill::Hold(Vec::new()),
+        // once every frame is encoded only the segments are left to join
+        framelist::GapPolicy::Placeholder(_) if frames.frames.is_empty() && resumed.is_some() => {
+            runner::GapFill::Warn
+        },
+        framelist::GapPolicy::Placeholder(color) => {
+            let reference = frames
+                .frames
+                .first()
+                .context("a placeholder needs at least one frame to copy the format from")?;
+            let mut data = ffmpeg
+                .render_placeholder(&reference.1, color)
+                .await
+                .context("failed to render placeholder frame")?;
+            if let Some(size) = normalize {
+                data = dimensions::normalize(&data, size)
+                    .context("failed to normalize placeholder frame")?
+                    .unwrap_or(data);
+            }
+            if args.alpha_key {
+                data = transparency::key_frame(&data).context("failed to key placeholder frame")?;
+            }
+            runner::GapFill::Placeholder(data)
+        },
+    };
+
+    let two_pass = match args.target_size {
+        Some(size) => {
+            if args.encoder != encoder::Codec::X264 || args.alpha.is_some() {
+                anyhow::bail!("a target size is only supported with the x264 encoder");
+            }
+            if args.jobs.get() > 1 {
+                anyhow::bail!("a target size can not be combined with parallel jobs");
+            }
+
+            let filled: u64 = match args.gaps {
+                framelist::GapPolicy::Hold | framelist::GapPolicy::Placeholder(_) => {
+                    gaps.iter().map(framelist::Gap::count).sum()
+                },
+                _ => 0,
+            };
+            let duration = (frames.frames.len() as u64 + filled) as f64 / args.fps as f64;
+            if !audio_tracks.is_empty() && !audio_settings.codec.is_lossy() {
+                anyhow::bail!(
+                    "a target size needs a lossy audio codec, the size of {} audio is unknown",
+                    audio_settings.codec
+                );
+            }
+            let audio_kbps = audio_tracks
+                .iter()
+                .map(|track| match track.bitrate.as_deref() {
+                    Some(bitrate) => bitrate::parse_kbps(bitrate),
+                    None => Ok(bitrate::DEFAULT_AUDIO_KBPS),
+                })
+                .sum::<anyhow::Result<u64>>()?;
+            let kbps = bitrate::video_kbps(size, duration, audio_kbps)
+                .context("unable to reach the target size")?;
+            info!(%size, %duration, %audio_kbps, video_kbps=%kbps, "two-pass encode");
+
+            Some(TwoPass {
+                kbps,
+                log: std::env::temp_dir().join(format!("vidgen-{}", std::process::id())),
+            })
+        },
+        None => None,
+    };
+
+    let capture = if args.thumbnail.is_empty() && args.poster.is_none() {
+        None
+    } else {
+        let relative = args.thumbnail.iter().chain(&args.poster).any(|s| s.is_relative());
+        if relative && args.follow {
+            anyhow::bail!("frames can not be chosen by percentage while following the source");
+        }
+        let target = Path::new(&args.target);
+        let dir = match args.thumbnail_dir.as_ref() {
+            Some(dir) => PathBuf::from(dir),
+            None => {
+                let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+                target.with_file_name(format!("{}_thumbnails", stem))
+            },
+        };
+        let capture = thumbnails::Capture::new(
+            &args.thumbnail,
+            args.poster,
+            &frames,
+            &range,
+            dir,
+            target,
+        )
+        .context("invalid frame selection")?;
+        Some(std::sync::Arc::new(capture))
+    };
+
+    let encode = Encode {
+        ffmpeg,
+        video_encoder,
+        input: (frame_width, frame_height),
+        output: (target_width, target_height),
+        two_pass,
+        audio: audio_tracks,
+        audio_graph,
+        audio_settings,
+        container,
+        renditions,
+        animation: args.animation.map(|format| animation::AnimationSettings {
+            format,
+            fps: args.animation_fps,
+            plays: args.plays,
+            dither: args.gif_dither,
+            quality: args.webp_quality,
+        }),
+        capture,
+    };
+
+    let ts_start = time::Instant::now();
+    let total = frames.frames.len() as u64;
+
+    let quirks = if let Some(ks) = args.keysight {
+        warn!(config=%ks, "entering quirks mode");
+        if ks.progress {
+            Some(quirks::KeysightQuirks::start(source_path.clone(), total))
+        } else {
+            None
+        }
+    } else {
+        None
+    };
+
+    let progress = progress::ConsoleProgress::start(total, !args.no_progress);
+
+    let totals = progress::EncoderTotals::default();
+    let result = if args.segment_frames.is_some() || args.jobs.get() > 1 {
+        encode_segmented(
+            &args,
+            &encode,
+            journal.unwrap_or_default(),
+            range,
+            frames,
+            disposer,
+            gap_fill,
+            normalize,
+            &progress,
+            quirks.as_ref(),
+            &totals,
+        )
+        .await
+    } else {
+        let mut output = Output::Target;
+        let mut first_pass = Ok(());
+        if encode.two_pass.is_some() {
+            // both passes read every frame, so only the second one may dispose of them
+            info!("starting first pass");
+            progress.set_total(total * 2);
+            let keep = disposal::FrameDisposal::Keep.open().await?;
+            let com = encode.command(&args, Path::new(&args.target), Output::FirstPass);
+            let runner = runner::Runner::start(
+                com,
+                frames.clone(),
+                keep,
+                None,
+                gap_fill.clone(),
+                args.alpha_key,
+                normalize,
+                None,
             )
+            .context("failed to start ffmpeg")?;
+            first_pass = drive(runner, &progress, quirks.as_ref(), &totals, 0)
+                .await
+                .context("first pass failed");
+            output = Output::SecondPass;
+        }
+
+        match first_pass {
+            Ok(()) => {
+                let com = encode.command(&args, Path::new(&args.target), output);
+
+                info!("starting runner");
+                let runner = runner::Runner::start(
+                    com,
+                    frames,
+                    disposer,
+                    follow,
+                    gap_fill,
+                    args.alpha_key,
+                    normalize,
+                    encode.capture.clone(),
+                )
+                .context("failed to start ffmpeg")?;
+                drive(runner, &progress, quirks.as_ref(), &totals, 0).await
+            },
+            Err(err) => Err(err),
+        }
+    };
+
+    if let Some(two_pass) = encode.two_pass.as_ref() {
+        remove_pass_logs(&two_pass.log).await;
+    }
+
+    let result = match (result, args.contact_sheet.as_ref(), encode.capture.as_ref()) {
+        (Ok(()), Some(sheet), Some(capture)) => thumbnails::contact_sheet(
+            capture.saved(),
+            args.contact_sheet_columns.get(),
+            args.contact_sheet_width.get(),
+            PathBuf::from(sheet),
+        )
+        .await
+        .context("failed to build contact sheet"),
+        (result, ..) => result,
+    };
+
+    match result.as_ref() {
+        Ok(()) => {
+            let time = ts_start.elapsed();
+            let mut msg = format!(
+                "done, took {}",
+                indicatif::HumanDuration(std::time::Duration::new(
+                    time.whole_seconds() as u64,
+                    time.subsec_nanoseconds() as u32
+                ))
+            );
+            if let Some((frame, out_time)) = totals.encoded() {
+                msg.push_str(&format!(", encoded {} frames", frame));
+                if time.as_seconds_f64() > 0.0 {
+                    msg.push_str(&format!(" at {:.2}x speed", out_time / time.as_seconds_f64()));
+                }
+            }
+            info!("{}", msg);
+            progress.finish(msg);
+        },
+        Err(_) => progress.abandon(),
+    }
+
+    if let Some(q) = quirks {
+        if let Err(err) = result.as_ref() {
+            let msg = err.chain().map(|cause| format!("{:#}", cause)).collect();
+            q.push_msg(quirks::QuirksMessage::Error { error: msg });
+        }
+        q.stop().await?;
+    }
+
+    result.map(|_| ())
+}
+
+/// Everything needed to build an encoding ffmpeg command.
+struct Encode {
+    ffmpeg:        ffmpeg::Ffmpeg,
+    video_encoder: encoder::VideoEncoder,
+    input:         (u32, u32),
+    output:        (u32, u32),
+    two_pass:      Option<TwoPass>,
+    audio:         Vec<audio::AudioTrack>,
+    /// filter_complex taking the audio inputs to the `[aN]` outputs, empty when copying
+    audio_graph:    String,
+    audio_settings: audio::AudioSettings,
+    /// Container of the final output, `None` lets ffmpeg guess from the extension
+    container:      Option<container::Container>,
+    renditions:     Vec<RenditionOutput>,
+    animation:      Option<animation::AnimationSettings>,
+    /// Copies of chosen frames, taken by every runner reading the target's frames
+    capture:        Option<std::sync::Arc<thumbnails::Capture>>,
+}
+
+/// An extra output of the main encode, see `--rendition`.
+struct RenditionOutput {
+    target:        PathBuf,
+    output:        (u32, u32),
+    video_encoder: encoder::VideoEncoder,
+    container:     Option<container::Container>,
+}
+
+/// Settings of a two-pass encode hitting a target bitrate.
+struct TwoPass {
+    kbps: u64,
+    /// Prefix of the encoder's pass log files
+    log:  PathBuf,
+}
+
+/// What an encoding ffmpeg command produces.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum Output {
+    /// The target
+    Target,
+    /// A segment, without audio and starting with a keyframe so it can be joined without
+    /// re-encoding
+    Segment,
+    /// Only the pass log of a two-pass encode
+    FirstPass,
+    /// The target of a two-pass encode
+    SecondPass,
+}
+
+impl Encode {
+    /// Build the ffmpeg command reading frames from stdin and writing to `target`.
+    fn command(&self, args: &Args, target: &Path, output: Output) -> Command {
+        let (frame_width, frame_height) = self.input;
+
+        let mut com = Command::new(self.ffmpeg.ffmpeg());
+        ffarg!(com, "-y");
+        ffarg!(com, "-nostats");
+        ffarg!(com, "-progress", "pipe:1");
+        ffarg!(com, "-framerate", args.fps.to_string());
+        ffarg!(com, "-s", format!("{frame_width}x{frame_height}"));
+        ffarg!(com, "-an");
+        if args.alpha_key {
+            // keyed frames are decoded by the runner and piped as raw rgba
+            ffarg!(com, "-f", "rawvideo");
+            ffarg!(com, "-pix_fmt", "rgba");
+        } else {
+            ffarg!(com, "-f", "image2pipe");
+        }
+        ffarg!(com, "-i", "-");
+        if matches!(output, Output::Target | Output::SecondPass) {
+            self.add_audio_inputs(&mut com);
+            self.map_audio(&mut com, 0);
+        }
+        let mut filters: Vec<String> = args
+            .scale_mode
+            .filter(args.scale_algo, self.input, self.output)
+            .into_iter()
+            .collect();
+        if let Some(alpha) = args.alpha {
+            info!(%alpha, "alpha output");
+            if encoder_options_given(args) {
+                warn!(encoder=%args.encoder, "encoder options are ignored for alpha output");
+            }
+            ffarg!(com, "-c:v", alpha.codec());
+            ffarg!(com, "-pix_fmt", alpha.pix_fmt());
+            com.args(alpha.extra_args());
+        } else if let Some(animation) = self.animation {
+            info!(?animation, "animated output");
+            filters.extend(animation.filter());
+            com.args(animation.args());
+        } else {
+            info!(video_encoder=?self.video_encoder, "video encoder");
+            add_video_encoder(&mut com, args, &self.video_encoder, &mut filters);
+            if let Some(two_pass) = self.two_pass.as_ref() {
+                let pass = match output {
+                    Output::FirstPass => "1",
+                    _ => "2",
+                };
+                ffarg!(com, "-b:v", format!("{}k", two_pass.kbps));
+                ffarg!(com, "-pass", pass);
+                ffarg!(com, "-passlogfile", &two_pass.log);
+            }
+        }
+        if !filters.is_empty() {
+            ffarg!(com, "-vf", filters.join(","));
+        }
+        if output == Output::Segment {
+            ffarg!(com, "-force_key_frames", "expr:eq(n,0)");
+        }
+
+        add_extra_args(&mut com, args);
+
+        if output == Output::FirstPass {
+            // the first pass only writes the pass log
+            ffarg!(com, "-f", "null");
+            add_target(&mut com, Path::new("-"), None);
+        } else if output == Output::Segment {
+            add_target(&mut com, target, Some(segment_container(args)));
+        } else {
+            add_target(&mut com, target, self.container);
+        }
+        if output == Output::Target {
+            for (idx, rendition) in self.renditions.iter().enumerate() {
+                self.add_rendition(&mut com, args, rendition, idx + 1);
+            }
+        }
+
+        finish_command(&mut com, args);
+        com
+    }
+
+    /// Add a rendition as output `out` of the command, after the main output.
+    fn add_rendition(
+        &self,
+        mut com: &mut Command,
+        args: &Args,
+        rendition: &RenditionOutput,
+        out: usize,
+    ) {
+        info!(
+            target=?rendition.target,
+            size=?rendition.output,
+            video_encoder=?rendition.video_encoder,
+            "rendition"
         );
+        self.map_audio(com, out);
+        let mut filters: Vec<String> = args
+            .scale_mode
+            .filter(args.scale_algo, self.input, rendition.output)
+            .into_iter()
+            .collect();
+        add_video_encoder(com, args, &rendition.video_encoder, &mut filters);
+        if !filters.is_empty() {
+            ffarg!(com, "-vf", filters.join(","));
+        }
+        add_target(com, &rendition.target, rendition.container);
+    }
+
+    /// Add the audio tracks as inputs 1 and up, the video is expected to be input 0.
+    fn add_audio_inputs(&self, mut com: &mut Command) {
+        for track in &self.audio {
+            info!(?track.file, %track.start, "requested audio, adding ffmpeg options");
+            ffarg!(com, "-i", &track.file);
+        }
+        if !self.audio_graph.is_empty() {
+            ffarg!(com, "-filter_complex", &self.audio_graph);
+        }
+    }
+
+    /// Map the video and audio tracks into output `out`, 0 being the main output.
+    fn map_audio(&self, mut com: &mut Command, out: usize) {
+        if self.audio.is_empty() {
+            return;
+        }
+
         ffarg!(com, "-map", "0:v:0");
-        ffarg!(com, "-map", "[a]");
-        ffarg!(com, "-c:a", "aac");
-        if let Some(bitrate) = audio.bitrate {
-            ffarg!(com, "-b:a", bitrate);
-        }
-    }
-    ffarg!(com, "-c:v", "libx264");
-    ffarg!(com, "-pix_fmt", "yuv420p");
-    ffarg!(com, "-preset:v", args.x264_preset.to_string());
-    ffarg!(
-        com,
-        "-vf",
-        format!("scale={target_width}x{target_height}:flags=bicubic")
-    );
+        for idx in 0..self.audio.len() {
+            if self.audio_graph.is_empty() {
+                ffarg!(com, "-map", format!("{}:a:0", idx + 1));
+            } else if out == 0 {
+                ffarg!(com, "-map", format!("[a{}]", idx));
+            } else {
+                ffarg!(com, "-map", format!("[a{}r{}]", idx, out));
+            }
+        }
+        com.args(self.audio_settings.args(&self.audio));
+    }
 
-    if let Some(tune) = args.x264_tune {
-        info!(?tune, "ffmpeg tuning");
-        ffarg!(com, "-tune", tune.to_string());
+    /// The ffmpeg arguments every segment in `dir` is encoded with.
+    fn segment_settings(&self, args: &Args, dir: &Path) -> String {
+        let com = self.command(args, &dir.join("segment"), Output::Segment);
+        com.as_std()
+            .get_args()
+            .map(|a| a.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ")
     }
 
-    if let Some(crf) = args.crf {
-        info!(?crf);
-        ffarg!(com, "-crf", crf.0.to_string());
+    /// Build the ffmpeg command joining all segments listed in `list` into the target.
+    fn concat_command(&self, args: &Args, list: &Path) -> Command {
+        let mut com = Command::new(self.ffmpeg.ffmpeg());
+        ffarg!(com, "-y");
+        ffarg!(com, "-nostats");
+        ffarg!(com, "-f", "concat");
+        ffarg!(com, "-safe", "0");
+        ffarg!(com, "-i", list);
+        self.add_audio_inputs(&mut com);
+        self.map_audio(&mut com, 0);
+        ffarg!(com, "-c:v", "copy");
+
+        add_target(&mut com, Path::new(&args.target), self.container);
+        finish_command(&mut com, args);
+        com
     }
+}
 
-    if let Some(extra_args) = args.extra_arg {
+/// Remove the pass log files the encoder wrote next to `log`.
+async fn remove_pass_logs(log: &Path) {
+    let (dir, prefix) = match (log.parent(), log.file_name()) {
+        (Some(dir), Some(prefix)) => (dir, prefix.to_string_lossy().into_owned()),
+        _ => return,
+    };
+    let mut entries = match tokio::fs::read_dir(dir).await {
+        Ok(entries) => entries,
+        Err(why) => {
+            warn!(error=%why, "failed to clean up pass logs");
+            return;
+        },
+    };
+
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if entry.file_name().to_string_lossy().starts_with(&format!("{}-", prefix)) {
+            if let Err(why) = tokio::fs::remove_file(entry.path()).await {
+                warn!(file=?entry.path(), error=%why, "failed to remove pass log");
+            }
+        }
+    }
+}
+
+fn add_extra_args(mut com: &mut Command, args: &Args) {
+    if let Some(extra_args) = args.extra_arg.as_ref() {
         let span = warn_span!("extra-args");
         let _guard = span.enter();
         for arg in extra_args {
@@ -231,10 +1005,43 @@ async fn program(args: Args) -> anyhow::Result<()> {
             }
         }
     }
+}
 
+/// Add the encoder, pixel format and colour options, converting to the pixel format at the end
+/// of `filters`.
+fn add_video_encoder(
+    mut com: &mut Command,
+    args: &Args,
+    video_encoder: &encoder::VideoEncoder,
+    filters: &mut Vec<String>,
+) {
+    let pix_fmt = args
+        .pix_fmt
+        .map(|p| p.to_string())
+        .unwrap_or_else(|| video_encoder.pix_fmt().to_owned());
+    let color = color::ColorSettings {
+        space: args.color_space,
+        range: args.color_range,
+    };
+    // convert explicitly, the implicit conversion ffmpeg inserts ignores the tags
+    filters.push(color.conversion(&pix_fmt));
+    ffarg!(com, "-c:v", video_encoder.codec_name());
+    ffarg!(com, "-pix_fmt", pix_fmt);
+    com.args(video_encoder.args());
+    com.args(color.args());
+}
+
+/// End the options of an output with its target.
+fn add_target(mut com: &mut Command, target: &Path, container: Option<container::Container>) {
     ffarg!(com, "-shortest");
-    ffarg!(com, args.target);
+    if let Some(container) = container {
+        ffarg!(com, "-f", container.muxer());
+        com.args(container.args());
+    }
+    ffarg!(com, target);
+}
 
+fn finish_command(com: &mut Command, args: &Args) {
     #[cfg(windows)]
     {
         const CREATE_NO_WINDOW: u32 = 0x08000000;
@@ -242,89 +1049,283 @@ async fn program(args: Args) -> anyhow::Result<()> {
     }
 
     if args.debug.enabled() {
-        debug!(command=?com, "ffmpeg encode");
+        debug!(command=?com, "ffmpeg command");
     }
+}
 
-    let source_path = PathBuf::from(args.source);
-
-    info!("starting runner");
-    let mut runner = runner::Runner::start(
-        com,
-        frames,
-        args.keysight.map(|v| v.delete_no_error).unwrap_or(false),
-    )
-    .context("failed to start ffmpeg")?;
-
-    let framen = match runner.event().await {
-        Some(Message::Start { frames }) => frames,
-        _ => anyhow::bail!("somehow missed start message"),
-    };
-
-    let quirks = if let Some(ks) = args.keysight {
-        warn!(config=%ks, "entering quirks mode");
-        if ks.progress {
-            Some(quirks::KeysightQuirks::start(source_path, framen))
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-
+/// Forward the events of a runner until it stops, recording its encoder statistics in `slot`.
+async fn drive(
+    mut runner: runner::RunnerHandle,
+    progress: &progress::ConsoleProgress,
+    quirks: Option<&quirks::KeysightQuirksHandle>,
+    totals: &progress::EncoderTotals,
+    slot: usize,
+) -> anyhow::Result<()> {
     while let Some(event) = runner.event().await {
         match event {
+            Message::Start { frames } => {
+                debug!(%frames, "runner started");
+            },
             Message::Frame { fid, path } => {
-                if let Some(q) = quirks.as_ref() {
+                progress.frame(&path);
+                if let Some(q) = quirks {
                     q.push_msg(quirks::QuirksMessage::Frame { fid, path });
                 }
             },
+            Message::Discovered { total } => {
+                debug!(%total, "new frames discovered");
+                progress.set_total(total);
+            },
+            Message::EncoderProgress {
+                frame,
+                fps,
+                bitrate,
+                out_time,
+                speed,
+            } => {
+                debug!(%frame, %fps, ?bitrate, %out_time, ?speed, "encoder progress");
+                let total = totals.update(slot, quirks::EncoderStats {
+                    frame,
+                    fps,
+                    bitrate,
+                    out_time: out_time.as_seconds_f64(),
+                    speed,
+                });
+                if let Some(q) = quirks {
+                    q.push_encoder(total);
+                }
+            },
             Message::Stop { time } => {
-                let msg = format!(
-                    "done, took {}",
-                    indicatif::HumanDuration(std::time::Duration::new(
-                        time.whole_seconds() as u64,
-                        time.subsec_nanoseconds() as u32
-                    ))
-                );
-                info!("{}", msg);
+                debug!(%time, "runner stopped");
                 break;
             },
-            Message::Start { frames: _ } => unreachable!("should never appear twice"),
         }
     }
+    totals.finish(slot);
 
-    let runner_res = runner.join().await.context("runner exited with error");
-    if let Some(q) = quirks {
-        if let Err(err) = runner_res.as_ref() {
-            let msg = err.chain().map(|cause| format!("{:#}", cause)).collect();
-            q.push_msg(quirks::QuirksMessage::Error { error: msg });
+    runner.join().await.context("runner exited with error")
+}
+
+/// Shared state of all segments of a segmented encode.
+struct Segmented<'a> {
+    args:      &'a Args,
+    encode:    &'a Encode,
+    dir:       PathBuf,
+    step:      u64,
+    gap_fill:  runner::GapFill,
+    normalize: Option<(u32, u32)>,
+    progress:  &'a progress::ConsoleProgress,
+    quirks:    Option<&'a quirks::KeysightQuirksHandle>,
+    totals:    &'a progress::EncoderTotals,
+    failed:    std::sync::atomic::AtomicBool,
+}
+
+impl Segmented<'_> {
+    /// Encode a single segment, keeping its frames. Nothing is encoded once another segment
+    /// failed.
+    async fn encode(
+        &self,
+        slot: usize,
+        chunk: Vec<Frame>,
+    ) -> anyhow::Result<Option<(segments::Segment, Vec<Frame>)>> {
+        if self.failed.load(Ordering::SeqCst) {
+            return Ok(None);
         }
-        q.stop().await?;
+
+        let ext = segment_container(self.args).to_string();
+        let segment = segments::Segment::new(
+            chunk.first().map(|f| f.0).unwrap_or_default(),
+            chunk.last().map(|f| f.0).unwrap_or_default(),
+            &ext,
+        );
+        info!(file=%segment.file, first=%segment.first, last=%segment.last, "encoding segment");
+
+        // frames are only disposed of once the segment is recorded as finished
+        let keep = disposal::FrameDisposal::Keep.open().await?;
+        let com = self
+            .encode
+            .command(self.args, &self.dir.join(&segment.file), Output::Segment);
+        let list = FrameList {
+            frames: chunk.clone(),
+            step:   self.step,
+        };
+        let runner = runner::Runner::start(
+            com,
+            list,
+            keep,
+            None,
+            self.gap_fill.clone(),
+            self.args.alpha_key,
+            self.normalize,
+            self.encode.capture.clone(),
+        )
+        .context("failed to start ffmpeg")?;
+        if let Err(err) = drive(runner, self.progress, self.quirks, self.totals, slot).await {
+            self.failed.store(true, Ordering::SeqCst);
+            return Err(err).with_context(|| format!("failed to encode segment {}", segment.file));
+        }
+
+        Ok(Some((segment, chunk)))
     }
+}
+
+/// Whether any option of the video encoder was given, these do not apply to alpha output.
+fn encoder_options_given(args: &Args) -> bool {
+    args.encoder != encoder::Codec::X264
+        || args.preset.is_some()
+        || args.quality.is_some()
+        || args.tune.is_some()
+        || args.crf.is_some()
+        || args.rate_control.is_some()
+        || args.x264_preset.is_some()
+        || args.x264_tune.is_some()
+}
 
-    runner_res
+/// Segments are muxed into the alpha container, or mkv which takes every other codec.
+fn segment_container(args: &Args) -> container::Container {
+    args.alpha
+        .map(|alpha| alpha.container())
+        .unwrap_or(container::Container::Mkv)
 }
 
-fn parse_resolution(s: &str) -> anyhow::Result<(u32, u32)> {
-    let p: Vec<_> = s.split('x').collect();
-    if p.len() != 2 {
-        anyhow::bail!("the dimension must be specified as `WIDTHxHEIGHT` (example: `1920x1080`)");
+/// Encode the frames into journaled segments, `jobs` at a time, disposing of the frames of a
+/// segment only once it is finished, then join all segments into the target.
+#[allow(clippy::too_many_arguments)]
+async fn encode_segmented(
+    args: &Args,
+    encode: &Encode,
+    mut journal: segments::Journal,
+    range: framelist::FrameRange,
+    frames: FrameList,
+    mut disposer: disposal::Disposer,
+    gap_fill: runner::GapFill,
+    normalize: Option<(u32, u32)>,
+    progress: &progress::ConsoleProgress,
+    quirks: Option<&quirks::KeysightQuirksHandle>,
+    totals: &progress::EncoderTotals,
+) -> anyhow::Result<()> {
+    let dir = segments::segment_dir(Path::new(&args.source));
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .context("failed to create segment directory")?;
+
+    let settings = encode.segment_settings(args, &dir);
+    if journal.segments.is_empty() {
+        journal.settings = settings;
+        journal.range = Some(range);
+        journal.last = frames.frames.last().map(|f| f.0);
+        journal.input = Some(encode.input);
+    } else if journal.settings != settings {
+        anyhow::bail!(
+            "the segments in {} were encoded with other settings, rerun with the same options \
+             or remove the directory to start over",
+            dir.display()
+        );
+    }
+    let step = frames.step;
+    let runs = journal.pending(frames.frames);
+    let remaining: usize = runs.iter().map(Vec::len).sum();
+    if !journal.segments.is_empty() {
+        info!(segments=%journal.segments.len(), %remaining, "resuming");
+        progress.set_total(remaining as u64);
+    }
+
+    let jobs = args.jobs.get();
+    let size = match args.segment_frames {
+        Some(size) => size.get(),
+        None => remaining.div_ceil(jobs),
+    };
+    let chunks: Vec<_> = runs
+        .into_iter()
+        .flat_map(|run| segments::split(run, size.max(1), step))
+        .collect();
+    info!(segments=%chunks.len(), %jobs, "encoding segments");
+
+    let segmented = Segmented {
+        args,
+        encode,
+        dir: dir.clone(),
+        step,
+        gap_fill,
+        normalize,
+        progress,
+        quirks,
+        totals,
+        failed: Default::default(),
+    };
+    let mut pending = futures::stream::iter(
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(slot, chunk)| segmented.encode(slot, chunk)),
+    )
+    .buffer_unordered(jobs);
+
+    // segments already running when one fails are still recorded, so their work is not lost
+    let mut failure = None;
+    while let Some(finished) = pending.next().await {
+        let (segment, chunk) = match finished {
+            Ok(Some(finished)) => finished,
+            Ok(None) => continue,
+            Err(err) => {
+                error!(error=%format!("{:#}", err), "segment failed");
+                failure.get_or_insert(err);
+                continue;
+            },
+        };
+        journal.insert(segment);
+        journal
+            .save(&dir)
+            .await
+            .context("failed to record finished segment")?;
+
+        for frame in &chunk {
+            disposer.dispose(frame).await?;
+        }
+    }
+    disposer.finish().await?;
+    if let Some(err) = failure {
+        return Err(err);
+    }
+
+    info!(segments=%journal.segments.len(), "joining segments");
+    let list = journal.write_concat_list(&dir).await?;
+    let mut child = encode
+        .concat_command(args, &list)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("failed to run ffmpeg")?;
+    let stderr = child
+        .stderr
+        .take()
+        .context("no stderr, is ffmpeg running?")?;
+    let stderr = runner::collect_stderr(stderr);
+    let (status, stderr) = tokio::join!(child.wait(), stderr);
+    let status = status.context("failed to wait for ffmpeg")?;
+    if !status.success() {
+        return Err(anyhow::anyhow!(stderr.join("\n")))
+            .with_context(|| format!("ffmpeg exited with {} while joining segments", status));
     }
 
-    let w = p[0].parse().context("width is not an integer")?;
-    let h = p[1].parse().context("height is not an integer")?;
-    Ok((w, h))
+    tokio::fs::remove_dir_all(&dir)
+        .await
+        .context("failed to remove segment directory")
 }
 
 #[instrument]
-async fn find_ident_frame(path: &str) -> anyhow::Result<String> {
+async fn find_ident_frame(
+    path: &str,
+    pattern: &framelist::FramePattern,
+) -> anyhow::Result<String> {
     let frame = ReadDirStream::new(
         tokio::fs::read_dir(path)
             .await
             .context("failed to list files in source directory")?,
     )
     .filter_map(|v| async { v.ok() })
-    .filter_map(framelist::FrameList::filter_item)
+    .filter_map(|e| framelist::FrameList::filter_item(e, pattern))
     .fold(framelist::Frame(u64::MAX, PathBuf::new()), |acc, e| async {
         if e.0 < acc.0 {
             e
@@ -354,6 +1355,41 @@ struct Args {
     #[clap()]
     target: String,
 
+    /// Frame file name pattern.
+    ///
+    /// Either a regex with a `(?P<frame>...)` group or a template like `frame_%05d.png` or
+    /// `img_####.png`
+    #[clap(long, default_value = framelist::DEFAULT_PATTERN)]
+    pattern: framelist::FramePattern,
+
+    /// First frame number to encode
+    #[clap(long)]
+    start_frame: Option<u64>,
+
+    /// Last frame number to encode
+    #[clap(long)]
+    end_frame: Option<u64>,
+
+    /// Only encode every n-th frame, counted from the first selected frame
+    #[clap(long, default_value = "1")]
+    step: std::num::NonZeroU64,
+
+    /// Encode at most this many frames
+    #[clap(long)]
+    max_frames: Option<u64>,
+
+    /// Read settings from this config file, on top of the user and source directory config
+    #[clap(long)]
+    config: Option<PathBuf>,
+
+    /// Apply the named profile from the config files
+    #[clap(long)]
+    profile: Option<String>,
+
+    /// Print the effective settings in config file format and exit
+    #[clap(long)]
+    print_config: bool,
+
     /// Dimensions of the frame files
     #[clap(short, long = "input-dim", default_value = "auto")]
     input_dim: String,
@@ -362,14 +1398,74 @@ struct Args {
     #[clap(short, long = "output-dim", default_value = "1920x1080")]
     output_dim: String,
 
+    /// How frames are fitted into the output dimensions.
+    ///
+    /// Avaliable options: stretch, fit[:COLOR], fill[:ANCHOR], integer[:COLOR], none. Anchors
+    /// are center, top, bottom, left, right, top-left, top-right, bottom-left and bottom-right.
+    /// Combine integer with `--scale-algo neighbor` for pixel art
+    #[clap(long, default_value = "stretch")]
+    scale_mode: scale::ScaleMode,
+
+    /// The scaling algorithm
+    #[clap(long, arg_enum, default_value = "bicubic")]
+    scale_algo: scale::ScaleAlgo,
+
+    /// Output pixel format, defaults to what the encoder prefers
+    #[clap(long, arg_enum, conflicts_with = "FORMAT")]
+    pix_fmt: Option<color::PixelFormat>,
+
+    /// Colour primaries, transfer and matrix the output is converted to and tagged with
+    #[clap(long, arg_enum, default_value = "bt709")]
+    color_space: color::ColorSpace,
+
+    /// Output colour range
+    #[clap(long, arg_enum, default_value = "limited")]
+    color_range: color::ColorRange,
+
     /// Target fps
     #[clap(short, long = "fps", default_value = "60")]
     fps: u16,
 
-    /// Instruct the encoder to use the given constant bitrate
+    /// The video encoder to use
+    #[clap(long, arg_enum, default_value = "x264")]
+    encoder: encoder::Codec,
+
+    /// Encoder preset, validated by the selected encoder.
+    ///
+    /// x264/x265: ultrafast..veryslow, vp9: best, good, realtime or cpu-used 0-8, svt-av1:
+    /// 0-13, aom-av1: cpu-used 0-8, prores: proxy, lt, standard, hq, 4444, 4444xq, ffv1: level
+    /// 1 or 3
+    #[clap(long, conflicts_with = "PRESET")]
+    preset: Option<String>,
+
+    /// Encoder quality, validated by the selected encoder.
+    ///
+    /// x264/x265: crf 0-51, vp9/av1: crf 0-63, prores: qscale 1-32, mpeg4: qscale 1-31
+    #[clap(long, conflicts_with = "crf")]
+    quality: Option<String>,
+
+    /// Encoder tuning, only supported by x264 and x265
+    #[clap(long, conflicts_with = "TUNING")]
+    tune: Option<String>,
+
+    /// Instruct the encoder to use the given constant rate factor
     #[clap(long, parse(try_from_str=x264::Crf::parse))]
     crf: Option<x264::Crf>,
 
+    /// x264 rate control as `MODE:VALUE[,maxrate=RATE][,bufsize=RATE]`.
+    ///
+    /// Modes are crf (0-51), cqp (0-69), abr and cbr (bitrates like 4500k or 6M). maxrate and
+    /// bufsize cap crf and abr, cbr only takes a bufsize which defaults to one second
+    #[clap(long, conflicts_with_all = &["crf", "quality"])]
+    rate_control: Option<x264::RateControl>,
+
+    /// Encode with two-pass x264 to end up at about this file size, like `49MB` or `1.5GiB`
+    #[clap(
+        long,
+        conflicts_with_all = &["crf", "quality", "rate-control", "follow", "segment-frames"]
+    )]
+    target_size: Option<bitrate::ByteSize>,
+
     /// Extra args passed as-is to ffmpeg. They will be included after the default arguments but before the output argument
     #[clap(long)]
     extra_arg: Option<Vec<String>>,
@@ -378,70 +1474,190 @@ struct Args {
     #[clap(long)]
     ffmpeg: Option<String>,
 
-    /// The x264 encoder preset to use
-    #[clap(long, arg_enum, default_value = "medium", name = "PRESET")]
-    x264_preset: x264::X264Preset,
+    /// The x264 encoder preset to use, medium if not given
+    #[clap(long, arg_enum, name = "PRESET")]
+    x264_preset: Option<x264::X264Preset>,
 
     /// The x264 encoder tuning to use
     #[clap(long, arg_enum, name = "TUNING")]
     x264_tune: Option<x264::X264Tune>,
 
+    /// Encode a video with an alpha channel in the given format instead of x264
+    #[clap(long, arg_enum, name = "FORMAT")]
+    alpha: Option<transparency::AlphaFormat>,
+
+    /// Derive the alpha channel by keying each frame
+    #[clap(long, requires = "FORMAT")]
+    alpha_key: bool,
+
+    /// Encode a looping animation instead of a video
+    #[clap(
+        long,
+        arg_enum,
+        name = "ANIMATION",
+        conflicts_with_all = &[
+            "FORMAT",
+            "audio",
+            "container",
+            "encoder",
+            "preset",
+            "quality",
+            "tune",
+            "crf",
+            "rate-control",
+            "PRESET",
+            "TUNING",
+            "pix-fmt",
+            "color-space",
+            "color-range",
+            "target-size",
+            "segment-frames"
+        ]
+    )]
+    animation: Option<animation::AnimationFormat>,
+
+    /// Frame rate of the animation, frames are dropped to reach it
+    #[clap(long, requires = "ANIMATION")]
+    animation_fps: Option<std::num::NonZeroU16>,
+
+    /// How often the animation plays, 0 loops forever
+    #[clap(long = "loop", default_value = "0", requires = "ANIMATION")]
+    plays: u32,
+
+    /// Dithering of the gif palette.
+    ///
+    /// Avaliable options: bayer[:SCALE], heckbert, floyd-steinberg, sierra2, sierra2-4a, none
+    #[clap(long, default_value = "bayer:2", requires = "ANIMATION")]
+    gif_dither: animation::Dither,
+
+    /// Quality of a webp animation, 0-100
+    #[clap(long, parse(try_from_str=animation::WebpQuality::parse), requires = "ANIMATION")]
+    webp_quality: Option<animation::WebpQuality>,
+
+    /// Save a copy of the chosen frames before they are disposed of, repeat for more.
+    ///
+    /// format: FRAME, PERCENT% of the selected frames or every:N frames
+    #[clap(long)]
+    thumbnail: Vec<thumbnails::FrameSelection>,
+
+    /// Directory for the thumbnails, next to the target if not given
+    #[clap(long, requires = "thumbnail")]
+    thumbnail_dir: Option<String>,
+
+    /// Save this frame next to the target as its poster, format: FRAME or PERCENT%
+    #[clap(long)]
+    poster: Option<thumbnails::FrameSelection>,
+
+    /// Assemble the thumbnails into a grid image labelled with their frame numbers
+    #[clap(long, requires = "thumbnail")]
+    contact_sheet: Option<String>,
+
+    /// Number of thumbnails in a row of the contact sheet
+    #[clap(long, default_value = "5", requires = "contact-sheet")]
+    contact_sheet_columns: std::num::NonZeroU32,
+
+    /// Width of a thumbnail on the contact sheet in pixels
+    #[clap(long, default_value = "256", requires = "contact-sheet")]
+    contact_sheet_width: std::num::NonZeroU32,
+
+    /// Output container, taken from the alpha format or the target extension if not given
+    #[clap(long, arg_enum)]
+    container: Option<container::Container>,
+
+    /// Encode another output from the same frames, repeat for more.
+    ///
+    /// format: [key=value,...]|target. Keys are size=WIDTHxHEIGHT, encoder, preset, quality,
+    /// tune and container, anything not given is taken from the main output. Extra args only
+    /// apply to the main output
+    #[clap(long, conflicts_with_all = &["FORMAT", "target-size", "segment-frames"])]
+    rendition: Vec<rendition::Rendition>,
+
     /// Wait for the user to press a button before exiting
     #[clap(short, long)]
     wait: bool,
 
-    /// Switch to keysight quirks mode. Avaliable options: progress, delete-no-error
+    /// What to do with each frame after it has been read.
+    ///
+    /// Avaliable options: delete, delete-no-error, keep, move:DIR, tar:FILE
+    #[clap(long, default_value = "delete")]
+    frame_disposal: disposal::FrameDisposal,
+
+    /// What to do about missing frame numbers.
+    ///
+    /// Avaliable options: error, warn, hold, placeholder[:COLOR]
+    #[clap(long, default_value = "warn")]
+    gaps: framelist::GapPolicy,
+
+    /// What to do about frames that are not the size of the input.
+    ///
+    /// Unless this is ignore, the header of every frame is read before encoding starts
+    #[clap(long, arg_enum, default_value = "error")]
+    size_mismatch: dimensions::SizeMismatch,
+
+    /// Encode in resumable segments of this many frames.
+    ///
+    /// Frames are only disposed of once their segment is finished, rerunning on the same source
+    /// continues after the last finished segment
+    #[clap(long, conflicts_with = "follow")]
+    segment_frames: Option<std::num::NonZeroUsize>,
+
+    /// Number of ffmpeg processes encoding segments at the same time.
+    ///
+    /// With more than one job the frames are split into segments, see `--segment-frames`
+    #[clap(short, long, default_value = "1", conflicts_with = "follow")]
+    jobs: std::num::NonZeroUsize,
+
+    /// Keep watching the source directory for new frames while encoding
     #[clap(long)]
-    keysight: Option<quirks::KeysightQuirksOptions>,
+    follow: bool,
 
-    /// Splice audio into video.
-    /// 
-    /// format: offset[,bitrate]|file
-    #[clap(long)]
-    audio: Option<AudioOptions>,
+    /// Stop following once a file with this name appears in the source directory
+    #[clap(long, default_value = "_vidgen.done", requires = "follow")]
+    follow_sentinel: String,
 
-    /// emit debug information to both stdout and a file
-    #[clap(arg_enum, long, default_value = "off")]
-    debug: DebugLevel,
+    /// Stop following after this many seconds without a new frame
+    #[clap(long, default_value = "300", requires = "follow")]
+    follow_timeout: u64,
 
-    /// Log additional data
+    /// Switch to keysight quirks mode. Avaliable options: progress, delete-no-error
     #[clap(long)]
-    extra_info: Vec<String>,
-}
+    keysight: Option<quirks::KeysightQuirksOptions>,
 
-#[derive(Debug)]
-struct AudioOptions {
-    start: f64,
-    file:  PathBuf,
-    bitrate: Option<String>
-}
+    /// Splice audio into video, repeat for more tracks.
+    ///
+    /// format: offset[,bitrate][,key=value...]|file, times are in seconds. Keys are
+    /// bitrate, trim=FROM-[TO], volume=DB, fade-in=SECONDS, fade-out=SECONDS and lang=CODE
+    #[clap(long)]
+    audio: Vec<audio::AudioTrack>,
 
-impl FromStr for AudioOptions {
-    type Err = anyhow::Error;
+    /// Audio codec, defaults to opus for webm and aac for everything else
+    #[clap(long, arg_enum)]
+    audio_codec: Option<audio::AudioCodec>,
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut fsplit = s.splitn(2, '|');
+    /// Audio sample rate in Hz
+    #[clap(long)]
+    audio_sample_rate: Option<u32>,
 
-        let ts = fsplit.next().context("why the fuck?")?;
-        let path = fsplit
-            .next()
-            .context("missing file path for audio file")?
-            .parse()
-            .context("the given path contains invalid characters")?;
+    /// Audio channel layout
+    #[clap(long, arg_enum)]
+    audio_channels: Option<audio::ChannelLayout>,
 
-        let mut option_parts = ts.split(',');
+    /// Audio quality, aac: vbr quality 0.1-2, opus: compression level 0-10, flac: compression
+    /// level 0-12
+    #[clap(long)]
+    audio_quality: Option<String>,
 
-        // the unwrap here will always succeed as split returns at least 1 element
-        let start = option_parts
-            .next()
-            .unwrap()
-            .parse()
-            .context("the start time needs to be the offset in milliseconds")?;
+    /// Do not show a progress bar on the console
+    #[clap(long)]
+    no_progress: bool,
 
-        let br = option_parts.next().map(String::from);
+    /// emit debug information to both stdout and a file
+    #[clap(arg_enum, long, default_value = "off")]
+    debug: DebugLevel,
 
-        Ok(AudioOptions { start, bitrate: br, file: path })
-    }
+    /// Log additional data
+    #[clap(long)]
+    extra_info: Vec<String>,
 }
 
 #[derive(Debug, serde::Deserialize)]
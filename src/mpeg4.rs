@@ -0,0 +1,23 @@
+use std::ops::RangeInclusive;
+
+use anyhow::Context;
+
+/// Fixed quantizer, lower is better.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd)]
+pub struct QScale(pub u8);
+
+const VALID_RANGE: RangeInclusive<u8> = 1..=31;
+impl QScale {
+    pub fn parse(s: &str) -> anyhow::Result<QScale> {
+        let p: u8 = s.parse().context("qscale is not a number")?;
+        if VALID_RANGE.contains(&p) {
+            Ok(QScale(p))
+        } else {
+            anyhow::bail!(
+                "qscale out of range: valid range is {} to {}",
+                VALID_RANGE.start(),
+                VALID_RANGE.end()
+            )
+        }
+    }
+}
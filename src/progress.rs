@@ -0,0 +1,127 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{io::IsTerminal, path::Path, sync::Mutex};
+
+use crate::quirks::EncoderStats;
+
+const TEMPLATE: &str =
+    "{spinner} [{elapsed_precise}] [{wide_bar}] {pos}/{len} frames, {per_sec}, ETA {eta} | {msg}";
+
+/// Console progress bar fed by the runner events, does nothing if stderr is not a terminal.
+///
+/// The bar is drawn to stderr so it does not get mixed up with the log lines on stdout.
+pub struct ConsoleProgress {
+    bar: Option<ProgressBar>,
+}
+
+impl ConsoleProgress {
+    pub fn start(total: u64, enabled: bool) -> Self {
+        if !enabled || !std::io::stderr().is_terminal() {
+            return ConsoleProgress { bar: None };
+        }
+
+        let bar = ProgressBar::with_draw_target(total, ProgressDrawTarget::stderr());
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template(TEMPLATE)
+                .progress_chars("=> "),
+        );
+        bar.enable_steady_tick(250);
+
+        ConsoleProgress { bar: Some(bar) }
+    }
+
+    pub fn frame(&self, path: &str) {
+        if let Some(bar) = self.bar.as_ref() {
+            let name = Path::new(path)
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_else(|| path.to_owned());
+            bar.inc(1);
+            bar.set_message(name);
+        }
+    }
+
+    pub fn set_total(&self, total: u64) {
+        if let Some(bar) = self.bar.as_ref() {
+            bar.set_length(total);
+        }
+    }
+
+    pub fn finish(&self, msg: String) {
+        if let Some(bar) = self.bar.as_ref() {
+            bar.finish_with_message(msg);
+        }
+    }
+
+    pub fn abandon(&self) {
+        if let Some(bar) = self.bar.as_ref() {
+            bar.abandon();
+        }
+    }
+}
+
+/// Combines the encoder statistics of all ffmpeg processes of a run, some of which may be
+/// running at the same time.
+#[derive(Default)]
+pub struct EncoderTotals {
+    slots: Mutex<Vec<Option<(EncoderStats, bool)>>>,
+}
+
+impl EncoderTotals {
+    /// Record the latest statistics of the process in `slot` and return the combined statistics.
+    ///
+    /// Frames and output time add up over all processes, rates only over the running ones.
+    pub fn update(&self, slot: usize, stats: EncoderStats) -> EncoderStats {
+        let mut slots = self.slots.lock().unwrap();
+        if slots.len() <= slot {
+            slots.resize(slot + 1, None);
+        }
+        slots[slot] = Some((stats, true));
+
+        let mut total = EncoderStats {
+            frame:    0,
+            fps:      0.0,
+            bitrate:  None,
+            out_time: 0.0,
+            speed:    None,
+        };
+        let mut bitrates = 0;
+        for (stats, running) in slots.iter().flatten() {
+            total.frame += stats.frame;
+            total.out_time += stats.out_time;
+            if !running {
+                continue;
+            }
+            total.fps += stats.fps;
+            if let Some(speed) = stats.speed {
+                total.speed = Some(total.speed.unwrap_or(0.0) + speed);
+            }
+            if let Some(bitrate) = stats.bitrate {
+                total.bitrate = Some(total.bitrate.unwrap_or(0.0) + bitrate);
+                bitrates += 1;
+            }
+        }
+        total.bitrate = total.bitrate.map(|b| b / bitrates as f64);
+
+        total
+    }
+
+    /// Mark the process in `slot` as done.
+    pub fn finish(&self, slot: usize) {
+        if let Some(Some((_, running))) = self.slots.lock().unwrap().get_mut(slot) {
+            *running = false;
+        }
+    }
+
+    /// Frames encoded and seconds of video written over all processes.
+    pub fn encoded(&self) -> Option<(u64, f64)> {
+        let slots = self.slots.lock().unwrap();
+        if slots.iter().all(Option::is_none) {
+            return None;
+        }
+
+        Some(slots.iter().flatten().fold((0, 0.0), |(frame, time), (stats, _)| {
+            (frame + stats.frame, time + stats.out_time)
+        }))
+    }
+}
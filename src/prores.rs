@@ -0,0 +1,58 @@
+use std::{fmt, ops::RangeInclusive};
+
+use anyhow::Context;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd, clap::ArgEnum)]
+pub enum ProresProfile {
+    Proxy,
+    Lt,
+    Standard,
+    Hq,
+    #[clap(name = "4444")]
+    P4444,
+    #[clap(name = "4444xq")]
+    P4444Xq,
+}
+
+impl fmt::Display for ProresProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ProresProfile::Proxy => "proxy",
+            ProresProfile::Lt => "lt",
+            ProresProfile::Standard => "standard",
+            ProresProfile::Hq => "hq",
+            ProresProfile::P4444 => "4444",
+            ProresProfile::P4444Xq => "4444xq",
+        };
+        f.write_str(s)
+    }
+}
+
+impl ProresProfile {
+    pub fn pix_fmt(&self) -> &'static str {
+        match self {
+            ProresProfile::P4444 | ProresProfile::P4444Xq => "yuv444p10le",
+            _ => "yuv422p10le",
+        }
+    }
+}
+
+/// Fixed quantizer, lower is better.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd)]
+pub struct QScale(pub u8);
+
+const VALID_RANGE: RangeInclusive<u8> = 1..=32;
+impl QScale {
+    pub fn parse(s: &str) -> anyhow::Result<QScale> {
+        let p: u8 = s.parse().context("qscale is not a number")?;
+        if VALID_RANGE.contains(&p) {
+            Ok(QScale(p))
+        } else {
+            anyhow::bail!(
+                "qscale out of range: valid range is {} to {}",
+                VALID_RANGE.start(),
+                VALID_RANGE.end()
+            )
+        }
+    }
+}
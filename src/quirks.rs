@@ -58,10 +58,22 @@ pub enum QuirksMessage {
     Error { error: Vec<String> },
 }
 
+/// Encoder side progress as reported by ffmpeg, `bitrate` is in kbit/s and `out_time` in
+/// seconds.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct EncoderStats {
+    pub frame:    u64,
+    pub fps:      f64,
+    pub bitrate:  Option<f64>,
+    pub out_time: f64,
+    pub speed:    Option<f64>,
+}
+
 pub struct KeysightQuirks {
     path:    PathBuf,
     kill:    oneshot::Receiver<()>,
     message: Arc<Mutex<Option<QuirksMessage>>>,
+    encoder: Arc<Mutex<Option<EncoderStats>>>,
     total:   u64,
 }
 
@@ -80,18 +92,21 @@ impl KeysightQuirks {
         let (kill_tx, kill_rx) = oneshot::channel();
         let last_message = Arc::new(Mutex::new(None));
         let handle_msg = Arc::clone(&last_message);
+        let encoder = Arc::new(Mutex::new(None));
         let quirks = KeysightQuirks {
             path,
             kill: kill_rx,
             message: handle_msg,
+            encoder: Arc::clone(&encoder),
             total,
         };
         let handle = tokio::spawn(quirks.run());
 
         KeysightQuirksHandle {
-            task:   handle,
-            events: last_message,
-            kill:   kill_tx,
+            task:    handle,
+            events:  last_message,
+            encoder,
+            kill:    kill_tx,
         }
     }
 
@@ -141,10 +156,11 @@ impl KeysightQuirks {
 
     async fn write_progress(&self, f: u64, t: u64, p: String, s: Status) -> anyhow::Result<()> {
         let json = serde_json::to_string(&ProgressFile {
-            status: s,
-            frames: f,
-            total:  t,
-            path:   p,
+            status:  s,
+            frames:  f,
+            total:   t,
+            path:    p,
+            encoder: self.encoder.lock().unwrap().clone(),
         })
         .context("failed to serialize json")?;
 
@@ -155,14 +171,17 @@ impl KeysightQuirks {
 }
 
 pub struct KeysightQuirksHandle {
-    task:   JoinHandle<anyhow::Result<()>>,
-    events: Arc<Mutex<Option<QuirksMessage>>>,
-    kill:   oneshot::Sender<()>,
+    task:    JoinHandle<anyhow::Result<()>>,
+    events:  Arc<Mutex<Option<QuirksMessage>>>,
+    encoder: Arc<Mutex<Option<EncoderStats>>>,
+    kill:    oneshot::Sender<()>,
 }
 
 impl KeysightQuirksHandle {
     pub fn push_msg(&self, msg: QuirksMessage) { *self.events.lock().unwrap() = Some(msg); }
 
+    pub fn push_encoder(&self, stats: EncoderStats) { *self.encoder.lock().unwrap() = Some(stats); }
+
     pub async fn stop(self) -> anyhow::Result<()> {
         let _ = self.kill.send(());
         self.task
@@ -175,10 +194,12 @@ impl KeysightQuirksHandle {
 #[derive(Debug, serde::Serialize)]
 struct ProgressFile {
     #[serde(flatten)]
-    status: Status,
-    frames: u64,
-    total:  u64,
-    path:   String,
+    status:  Status,
+    frames:  u64,
+    total:   u64,
+    path:    String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoder: Option<EncoderStats>,
 }
 
 #[derive(Debug, serde::Serialize)]
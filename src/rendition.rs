@@ -0,0 +1,137 @@
+//! Additional outputs encoded from the same pass over the frames.
+//!
+//! Frames can only be read once when they are disposed of, so every rendition is another output
+//! of the one ffmpeg command, each scaling the decoded frames to its own size.
+
+use anyhow::Context;
+use std::{path::PathBuf, str::FromStr};
+
+use crate::{
+    container::Container,
+    encoder::{self, Codec, EncoderSettings, VideoEncoder},
+    scale,
+    x264,
+};
+
+/// An extra output, settings that are not given are taken from the main output.
+#[derive(Debug, Clone)]
+pub struct Rendition {
+    pub target:    PathBuf,
+    pub size:      Option<(u32, u32)>,
+    pub encoder:   Option<Codec>,
+    pub settings:  EncoderSettings,
+    pub container: Option<Container>,
+}
+
+impl FromStr for Rendition {
+    type Err = anyhow::Error;
+
+    /// `[key=value,...]|target`, see the `--rendition` help for the keys.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (options, target) = s
+            .split_once('|')
+            .context("missing target path for rendition")?;
+        let target = target
+            .parse()
+            .context("the given path contains invalid characters")?;
+
+        let mut rendition = Rendition {
+            target,
+            size: None,
+            encoder: None,
+            settings: EncoderSettings::default(),
+            container: None,
+        };
+
+        for part in options.split(',').filter(|p| !p.trim().is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .with_context(|| format!("expected `key=value`, got `{}`", part))?;
+            let value = value.trim();
+
+            match key.trim() {
+                "size" => rendition.size = Some(scale::parse_resolution(value)?),
+                "encoder" => rendition.encoder = Some(encoder::parse_enum("encoder", value)?),
+                "preset" => rendition.settings.preset = Some(value.to_owned()),
+                "quality" => rendition.settings.quality = Some(value.to_owned()),
+                "tune" => rendition.settings.tune = Some(value.to_owned()),
+                "container" => {
+                    rendition.container = Some(encoder::parse_enum("container", value)?)
+                },
+                key => anyhow::bail!(
+                    "unknown rendition option `{}`, valid options are: size, encoder, preset, \
+                     quality, tune, container",
+                    key
+                ),
+            }
+        }
+
+        Ok(rendition)
+    }
+}
+
+impl Rendition {
+    /// The encoder of the rendition. With the encoder of the main output its settings are
+    /// inherited, otherwise the encoder defaults apply.
+    pub fn video_encoder(
+        &self,
+        main: Codec,
+        main_settings: &EncoderSettings,
+        x264: (x264::X264Preset, Option<x264::X264Tune>, Option<x264::RateControl>),
+    ) -> anyhow::Result<VideoEncoder> {
+        let codec = self.encoder.unwrap_or(main);
+        if codec != main {
+            return VideoEncoder::new(codec, &self.settings, (x264.0, x264.1, None));
+        }
+
+        let settings = EncoderSettings {
+            preset:  self.settings.preset.clone().or_else(|| main_settings.preset.clone()),
+            quality: self.settings.quality.clone().or_else(|| main_settings.quality.clone()),
+            tune:    self.settings.tune.clone().or_else(|| main_settings.tune.clone()),
+        };
+        VideoEncoder::new(codec, &settings, x264)
+    }
+
+    /// The container of the rendition, guessed from the target extension if not given.
+    pub fn container(&self) -> Option<Container> {
+        self.container.or_else(|| Container::from_target(&self.target))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_target_only() {
+        let r = "|review.mp4".parse::<Rendition>().unwrap();
+        assert_eq!(r.target, PathBuf::from("review.mp4"));
+        assert_eq!(r.size, None);
+        assert_eq!(r.encoder, None);
+        assert_eq!(r.container(), Some(Container::Mp4));
+
+        assert!("review.mp4".parse::<Rendition>().is_err());
+    }
+
+    #[test]
+    fn parse_options() {
+        let r = "size=1280x720, encoder=VP9,quality=32,container=mkv|review.webm"
+            .parse::<Rendition>()
+            .unwrap();
+        assert_eq!(r.size, Some((1280, 720)));
+        assert_eq!(r.encoder, Some(Codec::Vp9));
+        assert_eq!(r.settings.quality.as_deref(), Some("32"));
+        assert_eq!(r.settings.preset, None);
+        assert_eq!(r.container(), Some(Container::Mkv));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!("size=1280|review.mp4".parse::<Rendition>().is_err());
+        assert!("size=0x720|review.mp4".parse::<Rendition>().is_err());
+        assert!("encoder=h264|review.mp4".parse::<Rendition>().is_err());
+        assert!("container=avi|review.mp4".parse::<Rendition>().is_err());
+        assert!("fps=30|review.mp4".parse::<Rendition>().is_err());
+        assert!("quality|review.mp4".parse::<Rendition>().is_err());
+    }
+}
@@ -1,16 +1,29 @@
-use std::process::Stdio;
+use std::{
+    collections::{HashSet, VecDeque},
+    mem,
+    path::PathBuf,
+    process::Stdio,
+    sync::Arc,
+    time::Duration,
+};
 
 use anyhow::Context;
 use tokio::{
     fs::{self, File},
-    io::BufReader,
-    process::{Child, Command},
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader},
+    process::{Child, ChildStderr, ChildStdin, Command},
     sync::mpsc::{channel, Receiver, Sender},
     task::JoinHandle,
 };
 use tracing::Instrument;
 
-use crate::framelist::FrameList;
+use crate::{
+    dimensions,
+    disposal::Disposer,
+    framelist::{Frame, FrameList, FramePattern, FrameRange, Gap},
+    thumbnails::Capture,
+    transparency,
+};
 
 macro_rules! snd_chk {
     ($chs:expr) => {
@@ -23,32 +36,81 @@ macro_rules! snd_chk {
 #[derive(Debug)]
 pub struct Runner {
     child:  Child,
+    stderr: JoinHandle<Vec<String>>,
     notify: Sender<Message>,
     frames: FrameList,
 
-    delete_quirk: bool,
+    disposer: Disposer,
+    follow:   Option<FollowOptions>,
+    gaps:      GapFill,
+    key:       bool,
+    normalize: Option<(u32, u32)>,
+    capture:   Option<Arc<Capture>>,
+    last:      Option<u64>,
+}
+
+/// How the runner handles holes in the frame numbering.
+#[derive(Debug, Clone)]
+pub enum GapFill {
+    Error,
+    Warn,
+    /// Holds the data of the last streamed frame
+    Hold(Vec<u8>),
+    /// Holds the data of the placeholder frame
+    Placeholder(Vec<u8>),
+}
+
+/// Settings for picking up frames that are written while the runner is already encoding.
+#[derive(Debug, Clone)]
+pub struct FollowOptions {
+    pub source:        String,
+    pub sentinel:      PathBuf,
+    pub idle_timeout:  Duration,
+    pub poll_interval: Duration,
+    pub pattern:       FramePattern,
+    pub range:         FrameRange,
+    /// Fail on frames of another size, they were not part of the check before encoding
+    pub check_size:    Option<(u32, u32)>,
 }
 
 impl Runner {
+    #[allow(clippy::too_many_arguments)]
     pub fn start(
         mut command: Command,
         frames: FrameList,
-        delete_quirk: bool,
+        disposer: Disposer,
+        follow: Option<FollowOptions>,
+        gaps: GapFill,
+        key: bool,
+        normalize: Option<(u32, u32)>,
+        capture: Option<Arc<Capture>>,
     ) -> anyhow::Result<RunnerHandle> {
         debug!("starting ffmpeg child");
-        let child = command
+        let mut child = command
             .stdin(Stdio::piped())
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .spawn()
             .context("failed to start ffmpeg process")?;
+        let stderr = child
+            .stderr
+            .take()
+            .context("no stderr, is ffmpeg running?")?;
+        let stderr = tokio::spawn(collect_stderr(stderr).in_current_span());
         let (notify_tx, notify_rx) = channel(64);
 
         let runner = Runner {
             child,
+            stderr,
             notify: notify_tx,
             frames,
-            delete_quirk,
+            disposer,
+            follow,
+            gaps,
+            key,
+            normalize,
+            capture,
+            last: None,
         };
 
         debug!("starting task");
@@ -62,7 +124,7 @@ impl Runner {
 
     #[instrument(skip(self), name = "ffmpeg")]
     async fn run(mut self) -> anyhow::Result<()> {
-        let mut stdin = self
+        let stdin = self
             .child
             .stdin
             .take()
@@ -78,77 +140,355 @@ impl Runner {
                 .await
         );
 
+        // only spawned now so that no progress event can overtake the start message
+        let stdout = self
+            .child
+            .stdout
+            .take()
+            .context("no stdout, is ffmpeg running?")?;
+        let progress = tokio::spawn(collect_progress(stdout, self.notify.clone()).in_current_span());
+
         info!("starting encoding");
 
-        for frame in &self.frames.frames {
-            let frame_span = error_span!("frame", id=%frame.0, source=?frame.1.display());
-            let result = async {
-                snd_chk!(
-                    self.notify
-                        .send(Message::Frame {
-                            fid:  frame.0,
-                            path: frame.1.display().to_string(),
-                        })
-                        .await
-                );
-                trace!("opening file");
-                let mut file = BufReader::new(
-                    File::open(&frame.1)
-                        .in_current_span()
-                        .await
-                        .context("failed to open frame")?,
-                );
+        let fed = self.feed(stdin).await;
+
+        let disposed = self
+            .disposer
+            .finish()
+            .in_current_span()
+            .await
+            .context("failed to finish frame disposal");
+
+        info!("waiting for ffmpeg to finish up");
+        let status = self
+            .child
+            .wait()
+            .in_current_span()
+            .await
+            .context("failed to wait for ffmpeg")?;
+        let stderr = self.stderr.await.unwrap_or_default();
+        let _ = progress.await;
+
+        if !status.success() {
+            if let Err(why) = fed {
+                warn!(error=%why, "frame streaming failed");
+            }
+            error!(%status, "ffmpeg failed");
+            return Err(anyhow::anyhow!(stderr.join("\n")))
+                .with_context(|| format!("ffmpeg exited with {}", status));
+        }
+
+        fed?;
+        disposed?;
+
+        snd_chk!(
+            self.notify
+                .send(Message::Stop {
+                    time: ts_start.elapsed(),
+                })
+                .in_current_span()
+                .await
+        );
+
+        info!("done encoding");
+        Ok(())
+    }
 
-                trace!("copy data");
-                tokio::io::copy_buf(&mut file, &mut stdin)
+    /// Stream every frame to ffmpeg, closing its stdin once done.
+    async fn feed(&mut self, mut stdin: ChildStdin) -> anyhow::Result<()> {
+        let frames = mem::take(&mut self.frames.frames);
+        for frame in &frames {
+            self.stream_frame(&mut stdin, frame).await?;
+        }
+
+        if let Some(follow) = self.follow.take() {
+            let streamed = frames.iter().map(|f| f.0).collect();
+            self.follow(&mut stdin, follow, streamed).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn stream_frame(&mut self, stdin: &mut ChildStdin, frame: &Frame) -> anyhow::Result<()> {
+        let frame_span = error_span!("frame", id=%frame.0, source=?frame.1.display());
+        let result = async {
+            snd_chk!(
+                self.notify
+                    .send(Message::Frame {
+                        fid:  frame.0,
+                        path: frame.1.display().to_string(),
+                    })
+                    .await
+            );
+
+            if let Some(gap) = self
+                .last
+                .and_then(|l| Gap::between(l, frame.0, self.frames.step))
+            {
+                self.fill_gap(stdin, gap).in_current_span().await?;
+            }
+
+            trace!("opening file");
+            let mut file = BufReader::new(
+                File::open(&frame.1)
                     .in_current_span()
                     .await
-                    .context("failed to stream frame")?;
+                    .context("failed to open frame")?,
+            );
 
-                trace!("cleaning up");
-                let rmfr = fs::remove_file(&frame.1)
+            trace!("copy data");
+            if self.key || self.normalize.is_some() || matches!(self.gaps, GapFill::Hold(_)) {
+                let mut data = Vec::new();
+                file.read_to_end(&mut data)
                     .in_current_span()
                     .await
-                    .context("failed to remove frame");
-
-                if let Err(why) = rmfr {
-                    if self.delete_quirk {
-                        warn!(n=%frame.0, e=?why, "failed to delete frame");
-                    } else {
-                        return Err(why);
-                    }
+                    .context("failed to read frame")?;
+
+                if let Some(size) = self.normalize {
+                    trace!("normalizing");
+                    data = tokio::task::spawn_blocking(move || {
+                        dimensions::normalize(&data, size).map(|n| n.unwrap_or(data))
+                    })
+                    .await
+                    .context("normalize task failed")??;
                 }
 
-                trace!("cleaned up");
+                if self.key {
+                    trace!("keying");
+                    data = tokio::task::spawn_blocking(move || transparency::key_frame(&data))
+                        .await
+                        .context("keying task failed")??;
+                }
 
-                Ok::<(), anyhow::Error>(())
+                stdin
+                    .write_all(&data)
+                    .in_current_span()
+                    .await
+                    .context("failed to stream frame")?;
+
+                // the frame might be gone by the time the next one is missing, so keep its data
+                if let GapFill::Hold(held) = &mut self.gaps {
+                    *held = data;
+                }
+            } else {
+                tokio::io::copy_buf(&mut file, stdin)
+                    .in_current_span()
+                    .await
+                    .context("failed to stream frame")?;
             }
-            .instrument(frame_span)
-            .await;
+            self.last = Some(frame.0);
 
-            if let Err(why) = result {
-                error!(current_frame=%frame.0, error=%why, "error while reading frame");
-                return Err(why);
+            if let Some(capture) = self.capture.as_ref() {
+                capture.save(frame).in_current_span().await?;
             }
+
+            trace!("cleaning up");
+            self.disposer.dispose(frame).in_current_span().await?;
+
+            trace!("cleaned up");
+
+            Ok::<(), anyhow::Error>(())
         }
+        .instrument(frame_span)
+        .await;
 
-        drop(stdin);
+        if let Err(why) = result.as_ref() {
+            error!(current_frame=%frame.0, error=%why, "error while reading frame");
+        }
 
-        info!("waiting for ffmpeg to finish up");
-        let _ = self.child.wait().in_current_span().await;
+        result
+    }
 
-        snd_chk!(
-            self.notify
-                .send(Message::Stop {
-                    time: ts_start.elapsed(),
-                })
-                .in_current_span()
+    async fn fill_gap(&mut self, stdin: &mut ChildStdin, gap: Gap) -> anyhow::Result<()> {
+        let data = match &self.gaps {
+            GapFill::Error => anyhow::bail!("frames are missing: {}", gap),
+            GapFill::Warn => {
+                warn!(missing=%gap, "frames are missing");
+                return Ok(());
+            },
+            GapFill::Hold(held) => held,
+            GapFill::Placeholder(placeholder) => placeholder,
+        };
+
+        debug!(missing=%gap, "filling gap");
+        for _ in 0..gap.count() {
+            stdin
+                .write_all(data)
                 .await
-        );
+                .context("failed to stream gap filler")?;
+        }
 
-        info!("done encoding");
         Ok(())
     }
+
+    /// Keep polling the source directory for frames newer than the last one until either the
+    /// sentinel file shows up or no new frame was seen for the idle timeout.
+    async fn follow(
+        &mut self,
+        stdin: &mut ChildStdin,
+        opts: FollowOptions,
+        mut seen: HashSet<u64>,
+    ) -> anyhow::Result<()> {
+        let mut total = seen.len() as u64;
+        info!(sentinel=?opts.sentinel, timeout=?opts.idle_timeout, "following source directory");
+
+        let mut newest_seen = self.last;
+        let mut idle_since = std::time::Instant::now();
+        loop {
+            // check the sentinel before listing, so that every frame written before it is seen
+            let finished = fs::metadata(&opts.sentinel).await.is_ok();
+            let mut found = FrameList::from_dir(&opts.source, &opts.pattern)
+                .await
+                .context("failed to index frames")?
+                .frames;
+
+            let last = self.last;
+            found.retain(|f| {
+                if !opts.range.contains(f.0) {
+                    return false;
+                }
+                match last {
+                    Some(l) if f.0 <= l => {
+                        // frames are streamed in order, one showing up behind the last can not
+                        // be encoded anymore
+                        if seen.insert(f.0) {
+                            warn!(frame=%f.0, last=%l, "dropping frame that appeared late");
+                        }
+                        false
+                    },
+                    _ => true,
+                }
+            });
+            if let Some(max) = opts.range.max {
+                found.truncate(max.saturating_sub(total) as usize);
+            }
+
+            if let Some(newest) = found.last().map(|f| f.0) {
+                if newest_seen.map(|n| newest > n).unwrap_or(true) {
+                    newest_seen = Some(newest);
+                    idle_since = std::time::Instant::now();
+                }
+            }
+
+            let timed_out = idle_since.elapsed() >= opts.idle_timeout;
+            if !finished && !timed_out {
+                // the newest frame might still be in the process of being written
+                found.pop();
+            }
+
+            if let (Some(expected), false) = (opts.check_size, found.is_empty()) {
+                let mismatches = dimensions::scan(&found, expected)
+                    .await
+                    .context("failed to check frame sizes")?;
+                if !mismatches.is_empty() {
+                    let report = dimensions::report(&mismatches, expected);
+                    error!(%report, "frame size mismatch");
+                    anyhow::bail!("frame size mismatch, {}", report);
+                }
+            }
+
+            if !found.is_empty() {
+                total += found.len() as u64;
+                snd_chk!(self.notify.send(Message::Discovered { total }).await);
+
+                for frame in &found {
+                    seen.insert(frame.0);
+                    self.stream_frame(stdin, frame).await?;
+                }
+                continue;
+            }
+
+            if finished {
+                info!("sentinel file found, stopping");
+                break;
+            }
+
+            let past_end = matches!((opts.range.end, self.last), (Some(end), Some(l)) if l >= end);
+            let at_max = opts.range.max.map(|max| total >= max).unwrap_or(false);
+            if past_end || at_max {
+                info!("end of the frame range reached, stopping");
+                break;
+            }
+
+            if timed_out {
+                info!("no new frames within the idle timeout, stopping");
+                break;
+            }
+
+            tokio::time::sleep(opts.poll_interval).await;
+        }
+
+        Ok(())
+    }
+}
+
+/// Amount of ffmpeg stderr lines kept around for error reports.
+const STDERR_LINES: usize = 20;
+
+/// Read ffmpeg's stderr until it closes, keeping only the last [`STDERR_LINES`] lines.
+pub async fn collect_stderr(stderr: ChildStderr) -> Vec<String> {
+    let mut lines = VecDeque::with_capacity(STDERR_LINES);
+    let mut reader = BufReader::new(stderr).split(b'\n');
+
+    while let Ok(Some(chunk)) = reader.next_segment().await {
+        // ffmpeg rewrites its status line using carriage returns
+        for line in String::from_utf8_lossy(&chunk).split('\r') {
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+
+            trace!(target: "ffmpeg", "{}", line);
+            if lines.len() == STDERR_LINES {
+                lines.pop_front();
+            }
+            lines.push_back(line.to_owned());
+        }
+    }
+
+    lines.into()
+}
+
+/// Parse the key/value blocks ffmpeg writes with `-progress` and forward them as events.
+async fn collect_progress(stdout: impl AsyncRead + Unpin, notify: Sender<Message>) {
+    let mut lines = BufReader::new(stdout).lines();
+
+    let mut frame = 0;
+    let mut fps = 0.0;
+    let mut bitrate = None;
+    let mut out_time = time::Duration::ZERO;
+    let mut speed = None;
+    while let Ok(Some(line)) = lines.next_line().await {
+        let (key, value) = match line.split_once('=') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => continue,
+        };
+
+        match key {
+            "frame" => frame = value.parse().unwrap_or(frame),
+            "fps" => fps = value.parse().unwrap_or(fps),
+            "bitrate" => bitrate = value.trim_end_matches("kbits/s").parse().ok(),
+            "out_time_us" => {
+                if let Ok(us) = value.parse::<i64>() {
+                    out_time = time::Duration::microseconds(us.max(0));
+                }
+            },
+            "speed" => speed = value.trim_end_matches('x').parse().ok(),
+            "progress" => {
+                let msg = Message::EncoderProgress {
+                    frame,
+                    fps,
+                    bitrate,
+                    out_time,
+                    speed,
+                };
+                if notify.send(msg).await.is_err() {
+                    return;
+                }
+            },
+            _ => continue,
+        }
+    }
 }
 
 pub struct RunnerHandle {
@@ -166,5 +506,63 @@ impl RunnerHandle {
 pub enum Message {
     Start { frames: u64 },
     Frame { fid: u64, path: String },
+    Discovered { total: u64 },
+    /// Encoder side progress as reported by ffmpeg, `bitrate` is in kbit/s
+    EncoderProgress {
+        frame:    u64,
+        fps:      f64,
+        bitrate:  Option<f64>,
+        out_time: time::Duration,
+        speed:    Option<f64>,
+    },
     Stop { time: time::Duration },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn progress_blocks() {
+        let output = "frame=12\nfps=24.5\nbitrate= 812.3kbits/s\nout_time_us=500000\n\
+                      speed=1.5x\nprogress=continue\nframe=30\nbitrate=N/A\n\
+                      out_time_us=-1\nspeed=N/A\nprogress=end\n";
+        let (tx, mut rx) = channel(4);
+        collect_progress(output.as_bytes(), tx).await;
+
+        match rx.recv().await {
+            Some(Message::EncoderProgress {
+                frame,
+                fps,
+                bitrate,
+                out_time,
+                speed,
+            }) => {
+                assert_eq!(frame, 12);
+                assert_eq!(fps, 24.5);
+                assert_eq!(bitrate, Some(812.3));
+                assert_eq!(out_time, time::Duration::milliseconds(500));
+                assert_eq!(speed, Some(1.5));
+            },
+            other => panic!("unexpected message {:?}", other),
+        }
+        // values that are not available reset, the rest carries over
+        match rx.recv().await {
+            Some(Message::EncoderProgress {
+                frame,
+                fps,
+                bitrate,
+                out_time,
+                speed,
+            }) => {
+                assert_eq!(frame, 30);
+                assert_eq!(fps, 24.5);
+                assert_eq!(bitrate, None);
+                assert_eq!(out_time, time::Duration::ZERO);
+                assert_eq!(speed, None);
+            },
+            other => panic!("unexpected message {:?}", other),
+        }
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -0,0 +1,352 @@
+//! Fitting the input frames into the output dimensions.
+
+use anyhow::Context;
+use std::{fmt, str::FromStr};
+
+/// The ffmpeg scaler used to resize frames.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum ScaleAlgo {
+    Bicubic,
+    Bilinear,
+    Lanczos,
+    Spline,
+    /// Nearest neighbor, keeps hard pixel edges
+    Neighbor,
+    Area,
+}
+
+impl fmt::Display for ScaleAlgo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            ScaleAlgo::Bicubic => "bicubic",
+            ScaleAlgo::Bilinear => "bilinear",
+            ScaleAlgo::Lanczos => "lanczos",
+            ScaleAlgo::Spline => "spline",
+            ScaleAlgo::Neighbor => "neighbor",
+            ScaleAlgo::Area => "area",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Where the kept part of a cropped frame sits.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum Anchor {
+    Center,
+    Top,
+    Bottom,
+    Left,
+    Right,
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl fmt::Display for Anchor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Anchor::Center => "center",
+            Anchor::Top => "top",
+            Anchor::Bottom => "bottom",
+            Anchor::Left => "left",
+            Anchor::Right => "right",
+            Anchor::TopLeft => "top-left",
+            Anchor::TopRight => "top-right",
+            Anchor::BottomLeft => "bottom-left",
+            Anchor::BottomRight => "bottom-right",
+        };
+        f.write_str(s)
+    }
+}
+
+impl Anchor {
+    /// Offset of the kept area given the space left over on each axis.
+    fn offset(&self, (x, y): (u32, u32)) -> (u32, u32) {
+        let x = match self {
+            Anchor::Left | Anchor::TopLeft | Anchor::BottomLeft => 0,
+            Anchor::Right | Anchor::TopRight | Anchor::BottomRight => x,
+            _ => x / 2,
+        };
+        let y = match self {
+            Anchor::Top | Anchor::TopLeft | Anchor::TopRight => 0,
+            Anchor::Bottom | Anchor::BottomLeft | Anchor::BottomRight => y,
+            _ => y / 2,
+        };
+        (x, y)
+    }
+}
+
+/// How frames are brought to the output dimensions.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ScaleMode {
+    /// Scale to the output dimensions, ignoring the aspect ratio
+    Stretch,
+    /// Scale to fit inside the output dimensions and pad the rest with the given ffmpeg color
+    Fit(String),
+    /// Scale to cover the output dimensions and crop the overhang
+    Fill(Anchor),
+    /// Scale by the largest whole number that fits and pad the rest with the given ffmpeg color,
+    /// frames larger than the output are shrunk by a whole number dividing both sides instead.
+    /// Frames without such a divisor are fit.
+    Integer(String),
+    /// Keep the input dimensions
+    None,
+}
+
+impl fmt::Display for ScaleMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScaleMode::Stretch => f.write_str("stretch"),
+            ScaleMode::Fit(color) => write!(f, "fit:{}", color),
+            ScaleMode::Fill(anchor) => write!(f, "fill:{}", anchor),
+            ScaleMode::Integer(color) => write!(f, "integer:{}", color),
+            ScaleMode::None => f.write_str("none"),
+        }
+    }
+}
+
+impl FromStr for ScaleMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut split = s.splitn(2, ':');
+
+        // the unwrap here will always succeed as splitn returns at least 1 element
+        match (split.next().unwrap().trim(), split.next()) {
+            ("stretch", None) => Ok(ScaleMode::Stretch),
+            ("fit", None) => Ok(ScaleMode::Fit(String::from("black"))),
+            ("fit", Some(color)) => Ok(ScaleMode::Fit(color.trim().to_owned())),
+            ("fill", None) => Ok(ScaleMode::Fill(Anchor::Center)),
+            ("fill", Some(anchor)) => {
+                use clap::ArgEnum;
+                Anchor::from_str(anchor.trim(), true)
+                    .map(ScaleMode::Fill)
+                    .map_err(|_| anyhow::anyhow!("unknown anchor `{}`", anchor))
+            },
+            ("integer", None) => Ok(ScaleMode::Integer(String::from("black"))),
+            ("integer", Some(color)) => Ok(ScaleMode::Integer(color.trim().to_owned())),
+            ("none", None) => Ok(ScaleMode::None),
+            _ => anyhow::bail!(
+                "unknown scale mode, valid options are: stretch, fit[:COLOR], fill[:ANCHOR], \
+                 integer[:COLOR], none"
+            ),
+        }
+    }
+}
+
+impl ScaleMode {
+    /// The video filter bringing frames of size `input` to `output`, `None` if nothing is to be
+    /// done.
+    pub fn filter(
+        &self,
+        algo: ScaleAlgo,
+        input: (u32, u32),
+        output: (u32, u32),
+    ) -> Option<String> {
+        let scale = |(w, h): (u32, u32)| format!("scale={}x{}:flags={}", w, h, algo);
+        let pad = |inner: (u32, u32), color: &str| {
+            let (x, y) = Anchor::Center.offset((output.0 - inner.0, output.1 - inner.1));
+            format!(
+                "{},pad={}:{}:{}:{}:color={}",
+                scale(inner),
+                output.0,
+                output.1,
+                x,
+                y,
+                color
+            )
+        };
+
+        match self {
+            ScaleMode::Stretch => Some(scale(output)),
+            ScaleMode::Fit(color) => Some(pad(fit(input, output, false), color)),
+            ScaleMode::Integer(color) => {
+                let inner = integer(input, output).unwrap_or_else(|| {
+                    warn!(
+                        ?input,
+                        ?output,
+                        "no whole number shrinks the frames to fit, fitting them instead"
+                    );
+                    fit(input, output, false)
+                });
+                Some(pad(inner, color))
+            },
+            ScaleMode::Fill(anchor) => {
+                let outer = fit(input, output, true);
+                let (x, y) = anchor.offset((outer.0 - output.0, outer.1 - output.1));
+                Some(format!(
+                    "{},crop={}:{}:{}:{}",
+                    scale(outer),
+                    output.0,
+                    output.1,
+                    x,
+                    y
+                ))
+            },
+            ScaleMode::None => None,
+        }
+    }
+}
+
+/// Parse a resolution given as `WIDTHxHEIGHT`, both sides have to be above 0.
+pub fn parse_resolution(s: &str) -> anyhow::Result<(u32, u32)> {
+    let p: Vec<_> = s.split('x').collect();
+    if p.len() != 2 {
+        anyhow::bail!("the dimension must be specified as `WIDTHxHEIGHT` (example: `1920x1080`)");
+    }
+
+    let w = p[0].trim().parse().context("width is not an integer")?;
+    let h = p[1].trim().parse().context("height is not an integer")?;
+    if w == 0 || h == 0 {
+        anyhow::bail!("the width and height have to be above 0");
+    }
+    Ok((w, h))
+}
+
+/// Scale `input` keeping its aspect ratio until it fits inside `output`, or until it covers it
+/// with `cover`. One side always matches `output` exactly.
+fn fit(input: (u32, u32), output: (u32, u32), cover: bool) -> (u32, u32) {
+    let (iw, ih) = (input.0 as u64, input.1 as u64);
+    let (ow, oh) = (output.0 as u64, output.1 as u64);
+
+    // rounding must never push the scaled side past the output, or leave it short when covering
+    let clamp = |v: u64, o: u64| (if cover { v.max(o) } else { v.min(o) }) as u32;
+
+    // the input is relatively wider than the output
+    let wider = iw * oh > ih * ow;
+    if wider != cover {
+        (output.0, clamp(div_round(ih * ow, iw), oh))
+    } else {
+        (clamp(div_round(iw * oh, ih), ow), output.1)
+    }
+}
+
+/// Scale `input` by the largest whole number factor that fits inside `output`, or shrink it by
+/// the smallest whole number dividing both sides if it is larger. `None` if no such divisor
+/// makes it fit.
+fn integer(input: (u32, u32), output: (u32, u32)) -> Option<(u32, u32)> {
+    let factor = (output.0 / input.0).min(output.1 / input.1);
+    if factor > 0 {
+        return Some((input.0 * factor, input.1 * factor));
+    }
+
+    let least = input.0.div_ceil(output.0).max(input.1.div_ceil(output.1));
+    let common = gcd(input.0, input.1);
+    (least..=common)
+        .find(|d| common % d == 0)
+        .map(|d| (input.0 / d, input.1 / d))
+}
+
+fn div_round(a: u64, b: u64) -> u64 { (a + b / 2) / b }
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_modes() {
+        assert_eq!("stretch".parse::<ScaleMode>().unwrap(), ScaleMode::Stretch);
+        assert_eq!("fit".parse::<ScaleMode>().unwrap(), ScaleMode::Fit(String::from("black")));
+        assert_eq!(
+            "fit: 0x202020".parse::<ScaleMode>().unwrap(),
+            ScaleMode::Fit(String::from("0x202020"))
+        );
+        assert_eq!("fill".parse::<ScaleMode>().unwrap(), ScaleMode::Fill(Anchor::Center));
+        assert_eq!(
+            "fill:top-left".parse::<ScaleMode>().unwrap(),
+            ScaleMode::Fill(Anchor::TopLeft)
+        );
+        assert_eq!("none".parse::<ScaleMode>().unwrap(), ScaleMode::None);
+
+        assert!("fill:middle".parse::<ScaleMode>().is_err());
+        assert!("none:black".parse::<ScaleMode>().is_err());
+        assert!("zoom".parse::<ScaleMode>().is_err());
+    }
+
+    #[test]
+    fn parse_resolutions() {
+        assert_eq!(parse_resolution("1920x1080").unwrap(), (1920, 1080));
+        assert!(parse_resolution("1920").is_err());
+        assert!(parse_resolution("1920x1080x2").is_err());
+        assert!(parse_resolution("1920x-1").is_err());
+        assert!(parse_resolution("0x720").is_err());
+        assert!(parse_resolution("1280x0").is_err());
+    }
+
+    #[test]
+    fn fit_inside() {
+        assert_eq!(fit((1920, 1080), (1280, 1024), false), (1280, 720));
+        assert_eq!(fit((1000, 1000), (1920, 1080), false), (1080, 1080));
+        assert_eq!(fit((1920, 1080), (1280, 720), false), (1280, 720));
+        // 66.67 rounds up
+        assert_eq!(fit((3, 2), (100, 100), false), (100, 67));
+    }
+
+    #[test]
+    fn fit_cover() {
+        assert_eq!(fit((1920, 1080), (1280, 1024), true), (1820, 1024));
+        assert_eq!(fit((1000, 1000), (1920, 1080), true), (1920, 1920));
+        assert_eq!(fit((1920, 1080), (1080, 1080), true), (1920, 1080));
+    }
+
+    #[test]
+    fn filters() {
+        let algo = ScaleAlgo::Bicubic;
+        assert_eq!(
+            ScaleMode::Stretch.filter(algo, (1000, 1000), (1920, 1080)).unwrap(),
+            "scale=1920x1080:flags=bicubic"
+        );
+        assert_eq!(
+            ScaleMode::Fit(String::from("black"))
+                .filter(algo, (1000, 1000), (1920, 1080))
+                .unwrap(),
+            "scale=1080x1080:flags=bicubic,pad=1920:1080:420:0:color=black"
+        );
+        assert_eq!(
+            ScaleMode::Fill(Anchor::Top)
+                .filter(algo, (1920, 1080), (1080, 1080))
+                .unwrap(),
+            "scale=1920x1080:flags=bicubic,crop=1080:1080:420:0"
+        );
+        assert_eq!(ScaleMode::None.filter(algo, (1000, 1000), (1920, 1080)), None);
+    }
+
+    #[test]
+    fn integer_factors() {
+        assert_eq!(integer((320, 180), (1920, 1080)), Some((1920, 1080)));
+        assert_eq!(integer((300, 200), (1920, 1080)), Some((1500, 1000)));
+        assert_eq!(integer((1920, 1080), (1920, 1080)), Some((1920, 1080)));
+        // larger frames shrink by the smallest divisor of both sides that fits, 3 leaves a third
+        // of a pixel
+        assert_eq!(integer((4000, 3000), (1920, 1080)), Some((1000, 750)));
+        assert_eq!(integer((3840, 2160), (1920, 1080)), Some((1920, 1080)));
+        // 1921 and 1080 share no divisor
+        assert_eq!(integer((1921, 1080), (1920, 1080)), None);
+    }
+
+    #[test]
+    fn integer_filter() {
+        assert_eq!(
+            ScaleMode::Integer(String::from("black"))
+                .filter(ScaleAlgo::Neighbor, (300, 200), (1920, 1080))
+                .unwrap(),
+            "scale=1500x1000:flags=neighbor,pad=1920:1080:210:40:color=black"
+        );
+        // falls back to fit
+        assert_eq!(
+            ScaleMode::Integer(String::from("black"))
+                .filter(ScaleAlgo::Neighbor, (1921, 1080), (1920, 1080))
+                .unwrap(),
+            "scale=1920x1079:flags=neighbor,pad=1920:1080:0:0:color=black"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Resumable and parallel encoding: the frames are encoded into separate segment files, each
+//! finished segment is recorded in a journal and only then are its frames disposed of. A later
+//! run on the same source skips everything the journal covers and the segments are joined with
+//! the concat demuxer at the very end.
+
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+
+use crate::framelist::{Frame, FrameRange};
+
+/// Directory inside the source directory holding segments and the journal.
+const SEGMENT_DIR: &str = "_vidgen_segments";
+const JOURNAL: &str = "journal.json";
+const CONCAT_LIST: &str = "concat.txt";
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Segment {
+    /// File name, relative to the segment directory
+    pub file:  String,
+    pub first: u64,
+    pub last:  u64,
+}
+
+impl Segment {
+    /// Segments are named after their first frame, so that segments finishing out of order
+    /// never collide.
+    pub fn new(first: u64, last: u64, ext: &str) -> Self {
+        Segment {
+            file: format!("segment_{:010}.{}", first, ext),
+            first,
+            last,
+        }
+    }
+}
+
+/// The finished segments, ordered by their first frame.
+#[derive(Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct Journal {
+    /// The encoder arguments of the segments, segments made with other settings can not be
+    /// joined
+    #[serde(default)]
+    pub settings: String,
+    /// The frame range of the first run with its start filled in, and the last frame it
+    /// selected. A resumed encode selects the same frames, even once some of them are gone.
+    #[serde(default)]
+    pub range:    Option<FrameRange>,
+    #[serde(default)]
+    pub last:     Option<u64>,
+    /// The frame size of the first run, so resuming does not need a frame to identify it
+    #[serde(default)]
+    pub input:    Option<(u32, u32)>,
+    pub segments: Vec<Segment>,
+}
+
+pub fn segment_dir(source: &Path) -> PathBuf { source.join(SEGMENT_DIR) }
+
+impl Journal {
+    pub async fn load(dir: &Path) -> anyhow::Result<Self> {
+        let path = dir.join(JOURNAL);
+        if !path.exists() {
+            return Ok(Journal::default());
+        }
+
+        let data = fs::read(&path).await.context("failed to read journal")?;
+        serde_json::from_slice(&data).context("failed to parse journal")
+    }
+
+    /// Write the journal, replacing the old one only once the new one is complete.
+    pub async fn save(&self, dir: &Path) -> anyhow::Result<()> {
+        let json = serde_json::to_vec_pretty(self).context("failed to serialize journal")?;
+        let tmp = dir.join(format!("{}.tmp", JOURNAL));
+        fs::write(&tmp, json)
+            .await
+            .context("failed to write journal")?;
+        fs::rename(&tmp, dir.join(JOURNAL))
+            .await
+            .context("failed to replace journal")
+    }
+
+    /// The range to select the frames left to encode with. `given` has to agree with the range
+    /// of the first run, only its start may be left out.
+    pub fn resume(&self, given: FrameRange) -> anyhow::Result<FrameRange> {
+        let recorded = match self.range {
+            Some(recorded) => recorded,
+            None => return Ok(given),
+        };
+        let given = FrameRange {
+            start: given.start.or(recorded.start),
+            ..given
+        };
+        if given != recorded {
+            anyhow::bail!(
+                "the segments were encoded from another frame range, rerun with the same \
+                 --start-frame, --end-frame, --step and --max-frames"
+            );
+        }
+
+        // the first run already applied `max`, applying it again to the frames left would
+        // select frames past its last one
+        Ok(FrameRange {
+            end: self.last.or(recorded.end),
+            max: None,
+            ..recorded
+        })
+    }
+
+    pub fn insert(&mut self, segment: Segment) {
+        let at = self.segments.partition_point(|s| s.first < segment.first);
+        self.segments.insert(at, segment);
+    }
+
+    fn covers(&self, fid: u64) -> bool {
+        self.segments
+            .iter()
+            .any(|s| (s.first..=s.last).contains(&fid))
+    }
+
+    /// Drop all frames covered by a finished segment. The remaining frames are returned as runs
+    /// that do not span a finished segment, so a gap fill never encodes those frames again.
+    pub fn pending(&self, frames: Vec<Frame>) -> Vec<Vec<Frame>> {
+        let mut runs = Vec::new();
+        let mut current: Vec<Frame> = Vec::new();
+
+        for frame in frames {
+            if self.covers(frame.0) {
+                if !current.is_empty() {
+                    runs.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+
+            if let Some(prev) = current.last() {
+                if self.segments.iter().any(|s| prev.0 < s.first && s.last < frame.0) {
+                    runs.push(std::mem::take(&mut current));
+                }
+            }
+            current.push(frame);
+        }
+
+        if !current.is_empty() {
+            runs.push(current);
+        }
+
+        runs
+    }
+
+    /// Write the concat demuxer input listing all segments and return its path.
+    pub async fn write_concat_list(&self, dir: &Path) -> anyhow::Result<PathBuf> {
+        let list: String = self
+            .segments
+            .iter()
+            .map(|s| format!("file '{}'\n", s.file))
+            .collect();
+        let path = dir.join(CONCAT_LIST);
+        fs::write(&path, list)
+            .await
+            .context("failed to write concat list")?;
+        Ok(path)
+    }
+}
+
+/// Split frames into chunks of about `size` frames. A chunk only ends between two consecutive
+/// frames, so that filling a gap in the numbering never needs frames from another chunk.
+pub fn split(frames: Vec<Frame>, size: usize, step: u64) -> Vec<Vec<Frame>> {
+    let mut chunks = Vec::new();
+    let mut current: Vec<Frame> = Vec::new();
+
+    for frame in frames {
+        if current.len() >= size {
+            if let Some(prev) = current.last() {
+                if frame.0 == prev.0 + step {
+                    chunks.push(std::mem::take(&mut current));
+                }
+            }
+        }
+        current.push(frame);
+    }
+
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames(ids: &[u64]) -> Vec<Frame> {
+        ids.iter().map(|&fid| Frame(fid, PathBuf::new())).collect()
+    }
+
+    fn ids(chunks: &[Vec<Frame>]) -> Vec<Vec<u64>> {
+        chunks
+            .iter()
+            .map(|c| c.iter().map(|f| f.0).collect())
+            .collect()
+    }
+
+    #[test]
+    fn split_even() {
+        let chunks = split(frames(&[1, 2, 3, 4, 5, 6, 7]), 3, 1);
+        assert_eq!(ids(&chunks), vec![vec![1, 2, 3], vec![4, 5, 6], vec![7]]);
+    }
+
+    #[test]
+    fn split_never_at_a_gap() {
+        // 4 and 7 are missing, a chunk may only end where the next frame follows directly
+        let chunks = split(frames(&[1, 2, 3, 5, 6, 8, 9]), 3, 1);
+        assert_eq!(ids(&chunks), vec![vec![1, 2, 3, 5], vec![6, 8, 9]]);
+    }
+
+    #[test]
+    fn split_with_step() {
+        let chunks = split(frames(&[0, 2, 4, 6]), 2, 2);
+        assert_eq!(ids(&chunks), vec![vec![0, 2], vec![4, 6]]);
+
+        assert!(split(Vec::new(), 2, 1).is_empty());
+    }
+
+    #[test]
+    fn pending_skips_finished() {
+        let mut journal = Journal::default();
+        journal.insert(Segment::new(4, 6, "mkv"));
+        journal.insert(Segment::new(1, 3, "mkv"));
+        assert_eq!(journal.segments[0].first, 1);
+
+        let runs = journal.pending(frames(&[1, 2, 3, 4, 5, 6, 7, 8]));
+        assert_eq!(ids(&runs), vec![vec![7, 8]]);
+    }
+
+    #[test]
+    fn pending_splits_around_segments() {
+        let mut journal = Journal::default();
+        journal.insert(Segment::new(3, 4, "mkv"));
+        journal.insert(Segment::new(7, 8, "mkv"));
+
+        let runs = journal.pending(frames(&[1, 2, 3, 4, 5, 6, 7, 8, 9]));
+        assert_eq!(ids(&runs), vec![vec![1, 2], vec![5, 6], vec![9]]);
+
+        // frames of a finished segment that are gone still end the run
+        let runs = journal.pending(frames(&[2, 5, 6, 9]));
+        assert_eq!(ids(&runs), vec![vec![2], vec![5, 6], vec![9]]);
+    }
+
+    #[test]
+    fn resume_same_range() {
+        let range = |start, max| FrameRange { start, end: None, step: 2, max };
+        let journal = Journal {
+            range: Some(range(Some(10), Some(4))),
+            last: Some(16),
+            ..Journal::default()
+        };
+
+        // the start is filled in from the journal, `max` is replaced by the last frame
+        let resumed = journal.resume(range(None, Some(4))).unwrap();
+        assert_eq!(resumed, FrameRange { end: Some(16), ..range(Some(10), None) });
+
+        assert!(journal.resume(range(Some(12), Some(4))).is_err());
+        assert!(journal.resume(range(None, Some(3))).is_err());
+        assert!(journal.resume(FrameRange { step: 1, ..range(None, Some(4)) }).is_err());
+    }
+}
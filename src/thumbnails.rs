@@ -0,0 +1,383 @@
+//! Copies of chosen frames, taken while the runner streams them and before they are disposed
+//! of, and a contact sheet showing them in a grid.
+
+use anyhow::Context;
+use image::{imageops, Rgb, RgbImage};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    num::NonZeroU64,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use crate::framelist::{Frame, FrameList, FrameRange};
+
+/// Which frames to keep a copy of.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameSelection {
+    /// The frame with this number
+    Id(u64),
+    /// The frame this far into the selected frames
+    Percent(f64),
+    /// Every n-th frame from the start of the range
+    Every(NonZeroU64),
+}
+
+impl fmt::Display for FrameSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameSelection::Id(fid) => write!(f, "{}", fid),
+            FrameSelection::Percent(p) => write!(f, "{}%", p),
+            FrameSelection::Every(n) => write!(f, "every:{}", n),
+        }
+    }
+}
+
+impl FromStr for FrameSelection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(n) = s.strip_prefix("every:") {
+            let n = n.trim().parse().context("`every` needs a frame count above 0")?;
+            Ok(FrameSelection::Every(n))
+        } else if let Some(p) = s.strip_suffix('%') {
+            let p: f64 = p.trim().parse().context("the percentage is not a number")?;
+            if !(0.0..=100.0).contains(&p) {
+                anyhow::bail!("the percentage has to be between 0 and 100");
+            }
+            Ok(FrameSelection::Percent(p))
+        } else {
+            s.parse().map(FrameSelection::Id).context(
+                "unknown frame selection, valid options are: FRAME, PERCENT%, every:N",
+            )
+        }
+    }
+}
+
+impl FrameSelection {
+    pub fn is_relative(&self) -> bool { matches!(self, FrameSelection::Percent(_)) }
+}
+
+/// The frames to copy and where to, shared by all runners of an encode.
+#[derive(Debug)]
+pub struct Capture {
+    ids:    BTreeSet<u64>,
+    every:  Vec<NonZeroU64>,
+    start:  u64,
+    step:   u64,
+    dir:    PathBuf,
+    poster: Option<(u64, PathBuf)>,
+    /// The copies in `dir` made so far, by frame number
+    saved:  Mutex<BTreeMap<u64, PathBuf>>,
+}
+
+impl Capture {
+    /// Resolve the selections against the selected frames. `poster` is written next to the
+    /// target, with the extension of the frame.
+    pub fn new(
+        thumbnails: &[FrameSelection],
+        poster: Option<FrameSelection>,
+        frames: &FrameList,
+        range: &FrameRange,
+        dir: PathBuf,
+        target: &Path,
+    ) -> anyhow::Result<Self> {
+        let resolve = |selection: FrameSelection| match selection {
+            FrameSelection::Id(fid) => {
+                if !frames.frames.iter().any(|f| f.0 == fid) {
+                    warn!(%fid, "selected frame is not part of the encode");
+                }
+                Some(fid)
+            },
+            FrameSelection::Percent(p) => {
+                let last = frames.frames.len().checked_sub(1)?;
+                let idx = (last as f64 * p / 100.0).round() as usize;
+                frames.frames.get(idx).map(|f| f.0)
+            },
+            FrameSelection::Every(_) => None,
+        };
+
+        let poster = match poster {
+            Some(FrameSelection::Every(_)) => {
+                anyhow::bail!("the poster has to be a single frame number or percentage")
+            },
+            Some(selection) => resolve(selection).map(|fid| (fid, target.to_owned())),
+            None => None,
+        };
+
+        Ok(Capture {
+            ids: thumbnails.iter().filter_map(|s| resolve(*s)).collect(),
+            every: thumbnails
+                .iter()
+                .filter_map(|s| match s {
+                    FrameSelection::Every(n) => Some(*n),
+                    _ => None,
+                })
+                .collect(),
+            start: range.start.unwrap_or(0),
+            step: range.step,
+            dir,
+            poster,
+            saved: Mutex::new(BTreeMap::new()),
+        })
+    }
+
+    fn selected(&self, fid: u64) -> bool {
+        self.ids.contains(&fid)
+            || (fid >= self.start
+                && (fid - self.start) % self.step == 0
+                && self
+                    .every
+                    .iter()
+                    .any(|n| (fid - self.start) / self.step % n.get() == 0))
+    }
+
+    /// Copy the frame if it was chosen, before it is disposed of.
+    pub async fn save(&self, frame: &Frame) -> anyhow::Result<()> {
+        let Frame(fid, path) = frame;
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("png");
+
+        if let Some((poster, target)) = &self.poster {
+            if poster == fid {
+                let file = target.with_extension(format!("poster.{}", ext));
+                info!(%fid, file=%file.display(), "saving poster frame");
+                tokio::fs::copy(path, &file)
+                    .await
+                    .with_context(|| format!("failed to save poster frame to {}", file.display()))?;
+            }
+        }
+
+        if self.selected(*fid) {
+            tokio::fs::create_dir_all(&self.dir)
+                .await
+                .context("failed to create thumbnail directory")?;
+            let file = self.dir.join(path.file_name().context("frame has no file name")?);
+            debug!(%fid, file=%file.display(), "saving thumbnail");
+            tokio::fs::copy(path, &file)
+                .await
+                .with_context(|| format!("failed to save thumbnail to {}", file.display()))?;
+            self.saved.lock().unwrap().insert(*fid, file);
+        }
+
+        Ok(())
+    }
+
+    /// The thumbnails saved so far, ordered by frame number.
+    pub fn saved(&self) -> Vec<(u64, PathBuf)> {
+        self.saved
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(fid, file)| (*fid, file.clone()))
+            .collect()
+    }
+}
+
+/// 3x5 pixel digits, one bit per pixel, rows from the top.
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// Space between and around the tiles of a contact sheet.
+const SHEET_GAP: u32 = 4;
+
+/// Draw `number` onto the bottom left corner of `image`, white on a black box.
+fn label(image: &mut RgbImage, number: u64, scale: u32) {
+    let text = number.to_string();
+    let width = (text.len() as u32 * 4 + 1) * scale;
+    let height = 7 * scale;
+    let top = image.height().saturating_sub(height);
+
+    for y in top..image.height() {
+        for x in 0..width.min(image.width()) {
+            image.put_pixel(x, y, Rgb([0, 0, 0]));
+        }
+    }
+
+    for (idx, digit) in text.bytes().map(|b| (b - b'0') as usize).enumerate() {
+        let left = (idx as u32 * 4 + 1) * scale;
+        for (row, bits) in DIGITS[digit].iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let x = left + col * scale + dx;
+                        let y = top + (row as u32 + 1) * scale + dy;
+                        if x < image.width() && y < image.height() {
+                            image.put_pixel(x, y, Rgb([255, 255, 255]));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Lay out the thumbnails in a grid of `columns`, each scaled to `width`, and write it to
+/// `target`. The format follows the extension of the target.
+pub async fn contact_sheet(
+    thumbnails: Vec<(u64, PathBuf)>,
+    columns: u32,
+    width: u32,
+    target: PathBuf,
+) -> anyhow::Result<()> {
+    if thumbnails.is_empty() {
+        warn!("no thumbnails were saved, skipping contact sheet");
+        return Ok(());
+    }
+
+    tokio::task::spawn_blocking(move || {
+        // the first frame decides the tile size, frames of another size are stretched
+        let (first_width, first_height) = image::image_dimensions(&thumbnails[0].1)
+            .with_context(|| format!("failed to read thumbnail {}", thumbnails[0].1.display()))?;
+        let height = (width as u64 * first_height as u64 / first_width.max(1) as u64).max(1) as u32;
+        let columns = columns.min(thumbnails.len() as u32);
+        let rows = (thumbnails.len() as u32).div_ceil(columns);
+        let scale = (width / 64).max(2);
+
+        let mut sheet = RgbImage::from_pixel(
+            columns * (width + SHEET_GAP) + SHEET_GAP,
+            rows * (height + SHEET_GAP) + SHEET_GAP,
+            Rgb([32, 32, 32]),
+        );
+        // only one full sized frame is decoded at a time
+        for (idx, (fid, path)) in thumbnails.iter().enumerate() {
+            let image = image::open(path)
+                .with_context(|| format!("failed to read thumbnail {}", path.display()))?
+                .into_rgb8();
+            let mut tile = imageops::resize(&image, width, height, imageops::FilterType::Triangle);
+            drop(image);
+            label(&mut tile, *fid, scale);
+
+            let (col, row) = (idx as u32 % columns, idx as u32 / columns);
+            imageops::replace(
+                &mut sheet,
+                &tile,
+                (SHEET_GAP + col * (width + SHEET_GAP)) as i64,
+                (SHEET_GAP + row * (height + SHEET_GAP)) as i64,
+            );
+        }
+
+        info!(file=%target.display(), "writing contact sheet");
+        sheet
+            .save(&target)
+            .with_context(|| format!("failed to write contact sheet {}", target.display()))
+    })
+    .await
+    .context("contact sheet task failed")?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn capture(thumbnails: &[&str], poster: Option<&str>, range: FrameRange) -> Capture {
+        let frames = FrameList {
+            frames: (range.start.unwrap()..=range.end.unwrap())
+                .step_by(range.step as usize)
+                .map(|fid| Frame(fid, PathBuf::new()))
+                .collect(),
+            step:   range.step,
+        };
+        let thumbnails: Vec<_> = thumbnails
+            .iter()
+            .map(|s| s.parse::<FrameSelection>().unwrap())
+            .collect();
+        Capture::new(
+            &thumbnails,
+            poster.map(|s| s.parse::<FrameSelection>().unwrap()),
+            &frames,
+            &range,
+            PathBuf::from("thumbs"),
+            Path::new("video.mp4"),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_selections() {
+        assert_eq!(
+            "120".parse::<FrameSelection>().unwrap(),
+            FrameSelection::Id(120)
+        );
+        assert_eq!(
+            " 50% ".parse::<FrameSelection>().unwrap(),
+            FrameSelection::Percent(50.0)
+        );
+        assert_eq!(
+            "100%".parse::<FrameSelection>().unwrap(),
+            FrameSelection::Percent(100.0)
+        );
+        assert_eq!(
+            "every:30".parse::<FrameSelection>().unwrap(),
+            FrameSelection::Every(NonZeroU64::new(30).unwrap())
+        );
+
+        assert!("every:0".parse::<FrameSelection>().is_err());
+        assert!("every:".parse::<FrameSelection>().is_err());
+        assert!("101%".parse::<FrameSelection>().is_err());
+        assert!("-1%".parse::<FrameSelection>().is_err());
+        assert!("first".parse::<FrameSelection>().is_err());
+    }
+
+    #[test]
+    fn resolve_percentages() {
+        let range = FrameRange {
+            start: Some(10),
+            end:   Some(19),
+            step:  1,
+            max:   None,
+        };
+        let capture = capture(&["0%", "50%", "100%"], Some("25%"), range);
+        assert_eq!(
+            capture.ids.iter().copied().collect::<Vec<_>>(),
+            vec![10, 15, 19]
+        );
+        assert_eq!(capture.poster.as_ref().map(|p| p.0), Some(12));
+    }
+
+    #[test]
+    fn every_nth_selected_frame() {
+        let range = FrameRange {
+            start: Some(10),
+            end:   Some(40),
+            step:  2,
+            max:   None,
+        };
+        let capture = capture(&["every:3", "13"], None, range);
+        let selected: Vec<u64> = (0..=40).filter(|&fid| capture.selected(fid)).collect();
+        assert_eq!(selected, vec![10, 13, 16, 22, 28, 34, 40]);
+    }
+
+    #[test]
+    fn poster_needs_one_frame() {
+        let frames = FrameList {
+            frames: Vec::new(),
+            step:   1,
+        };
+        let range = FrameRange {
+            start: None,
+            end:   None,
+            step:  1,
+            max:   None,
+        };
+        let poster = Some("every:10".parse::<FrameSelection>().unwrap());
+        assert!(
+            Capture::new(&[], poster, &frames, &range, PathBuf::new(), Path::new("a")).is_err()
+        );
+    }
+}
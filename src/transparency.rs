@@ -1,14 +1,106 @@
-//! This is a thought experiment of eventually adding
-//! transparency support to vidgen. This would require
-//! a different output format which in turn has different
-//! options on ffmpeg, might be a bit of a pain to support.
+//! Output of videos with an alpha channel.
 //!
-//! Additionally following libs would be needed
-//!
-//! - image
-//!
-//! Due to the above difficulties this step is skipped for now.
+//! Frames are either passed through as-is (the encoder keeps the alpha channel of the
+//! source frames), or decoded and run through a keying transform ([`process_px`]) that
+//! derives the alpha channel from the color data before being handed to ffmpeg as raw rgba.
+
+use crate::container::Container;
+use anyhow::Context;
+use std::fmt;
+
+/// Alpha capable output formats.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, clap::ArgEnum)]
+pub enum AlphaFormat {
+    /// ProRes 4444 in a mov container
+    Prores4444,
+    /// VP9 with alpha in a webm container
+    Vp9,
+    /// PNG frames in a mov container
+    Png,
+    /// QuickTime Animation in a mov container
+    Qtrle,
+}
+
+impl fmt::Display for AlphaFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            AlphaFormat::Prores4444 => "prores4444",
+            AlphaFormat::Vp9 => "vp9",
+            AlphaFormat::Png => "png",
+            AlphaFormat::Qtrle => "qtrle",
+        };
+        f.write_str(s)
+    }
+}
+
+impl AlphaFormat {
+    pub fn codec(&self) -> &'static str {
+        match self {
+            AlphaFormat::Prores4444 => "prores_ks",
+            AlphaFormat::Vp9 => "libvpx-vp9",
+            AlphaFormat::Png => "png",
+            AlphaFormat::Qtrle => "qtrle",
+        }
+    }
+
+    pub fn pix_fmt(&self) -> &'static str {
+        match self {
+            AlphaFormat::Prores4444 => "yuva444p10le",
+            AlphaFormat::Vp9 => "yuva420p",
+            AlphaFormat::Png => "rgba",
+            AlphaFormat::Qtrle => "argb",
+        }
+    }
 
+    /// The codec of the encoded stream, as named by ffprobe.
+    pub fn stream_codec(&self) -> &'static str {
+        match self {
+            AlphaFormat::Prores4444 => "prores",
+            AlphaFormat::Vp9 => "vp9",
+            AlphaFormat::Png => "png",
+            AlphaFormat::Qtrle => "qtrle",
+        }
+    }
+
+    /// The container used when none is given.
+    pub fn container(&self) -> Container {
+        match self {
+            AlphaFormat::Vp9 => Container::Webm,
+            _ => Container::Mov,
+        }
+    }
+
+    /// Encoder options needed to actually keep the alpha channel.
+    pub fn extra_args(&self) -> &'static [&'static str] {
+        match self {
+            AlphaFormat::Prores4444 => &["-profile:v", "4444"],
+            AlphaFormat::Vp9 => &["-auto-alt-ref", "0"],
+            _ => &[],
+        }
+    }
+}
+
+/// Decode an encoded frame, apply the keying transform and return raw rgba data.
+pub fn key_frame(data: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let image = image::load_from_memory(data)
+        .context("failed to decode frame")?
+        .into_rgba8();
+
+    let mut pixels: Vec<Rgba> = image
+        .as_raw()
+        .chunks_exact(4)
+        .map(|p| Rgba {
+            r: p[0],
+            g: p[1],
+            b: p[2],
+            a: p[3],
+        })
+        .collect();
+
+    process(&mut pixels);
+
+    Ok(pixels.into_iter().flat_map(|p| [p.r, p.g, p.b, p.a]).collect())
+}
 
 // -- This main function uses some test data on a 4x4 grid to visualize the transformation
 // fn main() {
@@ -49,8 +141,10 @@ pub struct Rgba {
     pub a: u8,
 }
 
+#[allow(non_camel_case_types, clippy::upper_case_acronyms)]
 pub type FLOAT = f64;
 pub type RgbaF = (FLOAT, FLOAT, FLOAT, FLOAT);
+#[allow(dead_code)]
 pub type RgbF = (FLOAT, FLOAT, FLOAT);
 
 impl Rgba {
@@ -88,8 +182,8 @@ fn lerp(v: FLOAT, l: FLOAT, h: FLOAT) -> FLOAT {
 }
 
 pub fn process(image: &mut [Rgba]) {
-    for idx in 0..image.len() {
-        image[idx] = process_px(image[idx]);
+    for px in image.iter_mut() {
+        *px = process_px(*px);
     }
 }
 
@@ -0,0 +1,60 @@
+use std::{fmt, ops::RangeInclusive};
+
+use anyhow::Context;
+
+/// The libvpx quality/speed tradeoff, set via `-deadline`.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd, clap::ArgEnum)]
+pub enum Vp9Deadline {
+    Best,
+    Good,
+    Realtime,
+}
+
+impl fmt::Display for Vp9Deadline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Vp9Deadline::Best => "best",
+            Vp9Deadline::Good => "good",
+            Vp9Deadline::Realtime => "realtime",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd)]
+pub struct CpuUsed(pub u8);
+
+const CPU_USED_RANGE: RangeInclusive<u8> = 0..=8;
+impl CpuUsed {
+    pub fn parse(s: &str) -> anyhow::Result<CpuUsed> {
+        let p: u8 = s.parse().context("cpu-used is not a number")?;
+        if CPU_USED_RANGE.contains(&p) {
+            Ok(CpuUsed(p))
+        } else {
+            anyhow::bail!(
+                "cpu-used out of range: valid range is {} to {}",
+                CPU_USED_RANGE.start(),
+                CPU_USED_RANGE.end()
+            )
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd)]
+pub struct Crf(pub u8);
+
+const CRF_RANGE: RangeInclusive<u8> = 0..=63;
+impl Crf {
+    pub fn parse(s: &str) -> anyhow::Result<Crf> {
+        let p: u8 = s.parse().context("crf is not a number")?;
+        if CRF_RANGE.contains(&p) {
+            Ok(Crf(p))
+        } else {
+            anyhow::bail!(
+                "crf out of range: valid range is {} to {}",
+                CRF_RANGE.start(),
+                CRF_RANGE.end()
+            )
+        }
+    }
+}
@@ -1,7 +1,9 @@
-use std::{fmt, ops::RangeInclusive};
+use std::{fmt, ops::RangeInclusive, str::FromStr};
 
 use anyhow::Context;
 
+use crate::bitrate;
+
 #[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd, clap::ArgEnum)]
 pub enum X264Preset {
     Ultrafast,
@@ -74,3 +76,300 @@ impl Crf {
         }
     }
 }
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd)]
+pub struct Qp(pub u8);
+
+const QP_RANGE: RangeInclusive<u8> = 0..=69;
+impl Qp {
+    pub fn parse(s: &str) -> anyhow::Result<Qp> {
+        let p: u8 = s.parse().context("qp is not a number")?;
+        if QP_RANGE.contains(&p) {
+            Ok(Qp(p))
+        } else {
+            anyhow::bail!(
+                "qp out of range: valid range is {} to {}",
+                QP_RANGE.start(),
+                QP_RANGE.end()
+            )
+        }
+    }
+}
+
+/// Video buffering verifier limits, rates are in kbit/s.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub struct Vbv {
+    pub maxrate: u64,
+    pub bufsize: u64,
+}
+
+/// How x264 spends bits, all rates are in kbit/s.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash)]
+pub enum RateControl {
+    /// Constant rate factor, optionally capped
+    Crf { crf: Crf, vbv: Option<Vbv> },
+    /// Constant quantizer
+    Cqp(Qp),
+    /// Average bitrate, optionally capped
+    Abr { bitrate: u64, vbv: Option<Vbv> },
+    /// Constant bitrate, kept within `bufsize`
+    Cbr { bitrate: u64, bufsize: u64 },
+}
+
+impl fmt::Display for RateControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let vbv = |f: &mut fmt::Formatter<'_>, vbv: &Option<Vbv>| match vbv {
+            Some(v) => write!(f, ",maxrate={}k,bufsize={}k", v.maxrate, v.bufsize),
+            None => Ok(()),
+        };
+        match self {
+            RateControl::Crf { crf, vbv: v } => {
+                write!(f, "crf:{}", crf.0)?;
+                vbv(f, v)
+            },
+            RateControl::Cqp(qp) => write!(f, "cqp:{}", qp.0),
+            RateControl::Abr { bitrate, vbv: v } => {
+                write!(f, "abr:{}k", bitrate)?;
+                vbv(f, v)
+            },
+            RateControl::Cbr { bitrate, bufsize } => {
+                write!(f, "cbr:{}k,bufsize={}k", bitrate, bufsize)
+            },
+        }
+    }
+}
+
+impl FromStr for RateControl {
+    type Err = anyhow::Error;
+
+    /// `MODE:VALUE[,maxrate=RATE][,bufsize=RATE]`, like `crf:20,maxrate=6M,bufsize=12M` or
+    /// `cbr:4500k`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        // the unwrap here will always succeed as split returns at least 1 element
+        let mut mode = parts.next().unwrap().splitn(2, ':');
+        let (mode, value) = match (mode.next().unwrap().trim(), mode.next()) {
+            (mode, Some(value)) => (mode, value.trim()),
+            (mode, None) => anyhow::bail!("missing value for rate control mode `{}`", mode),
+        };
+
+        let mut maxrate = None;
+        let mut bufsize = None;
+        for part in parts {
+            let (key, rate) = part
+                .split_once('=')
+                .with_context(|| format!("expected `key=rate`, got `{}`", part))?;
+            let rate = bitrate::parse_kbps(rate)?;
+            match key.trim() {
+                "maxrate" => maxrate = Some(rate),
+                "bufsize" => bufsize = Some(rate),
+                key => anyhow::bail!("unknown rate control option `{}`", key),
+            }
+        }
+
+        let vbv = || match (maxrate, bufsize) {
+            (Some(maxrate), Some(bufsize)) => Ok(Some(Vbv { maxrate, bufsize })),
+            (None, None) => Ok(None),
+            _ => Err(anyhow::anyhow!("maxrate and bufsize have to be given together")),
+        };
+
+        let rc = match mode {
+            "crf" => RateControl::Crf {
+                crf: Crf::parse(value)?,
+                vbv: vbv()?,
+            },
+            "cqp" => {
+                if maxrate.is_some() || bufsize.is_some() {
+                    anyhow::bail!("constant qp can not be limited by maxrate and bufsize");
+                }
+                RateControl::Cqp(Qp::parse(value)?)
+            },
+            "abr" => {
+                let bitrate = bitrate::parse_kbps(value)?;
+                let vbv = vbv()?;
+                if let Some(vbv) = vbv {
+                    if vbv.maxrate < bitrate {
+                        anyhow::bail!("maxrate is below the average bitrate");
+                    }
+                }
+                RateControl::Abr { bitrate, vbv }
+            },
+            "cbr" => {
+                if maxrate.is_some() {
+                    anyhow::bail!("a constant bitrate is its own maxrate, only set bufsize");
+                }
+                let bitrate = bitrate::parse_kbps(value)?;
+                RateControl::Cbr {
+                    bitrate,
+                    // one second of video unless told otherwise
+                    bufsize: bufsize.unwrap_or(bitrate),
+                }
+            },
+            mode => anyhow::bail!(
+                "unknown rate control mode `{}`, valid modes are: crf, cqp, abr, cbr",
+                mode
+            ),
+        };
+
+        if let RateControl::Abr { bitrate: 0, .. } | RateControl::Cbr { bitrate: 0, .. } = rc {
+            anyhow::bail!("the bitrate must be above 0");
+        }
+
+        Ok(rc)
+    }
+}
+
+impl RateControl {
+    /// The ffmpeg options selecting this mode.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut push = |k: &str, v: String| {
+            args.push(k.to_owned());
+            args.push(v);
+        };
+        let kbps = |v: u64| format!("{}k", v);
+
+        match self {
+            RateControl::Crf { crf, vbv } => {
+                push("-crf", crf.0.to_string());
+                if let Some(vbv) = vbv {
+                    push("-maxrate", kbps(vbv.maxrate));
+                    push("-bufsize", kbps(vbv.bufsize));
+                }
+            },
+            RateControl::Cqp(qp) => push("-qp", qp.0.to_string()),
+            RateControl::Abr { bitrate, vbv } => {
+                push("-b:v", kbps(*bitrate));
+                if let Some(vbv) = vbv {
+                    push("-maxrate", kbps(vbv.maxrate));
+                    push("-bufsize", kbps(vbv.bufsize));
+                }
+            },
+            RateControl::Cbr { bitrate, bufsize } => {
+                push("-b:v", kbps(*bitrate));
+                push("-minrate", kbps(*bitrate));
+                push("-maxrate", kbps(*bitrate));
+                push("-bufsize", kbps(*bufsize));
+                // signal the constant rate in the stream and pad with filler data
+                push("-x264-params", String::from("nal-hrd=cbr"));
+            },
+        }
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_crf() {
+        assert_eq!(
+            "crf:20".parse::<RateControl>().unwrap(),
+            RateControl::Crf {
+                crf: Crf(20),
+                vbv: None,
+            }
+        );
+        assert_eq!(
+            "crf: 18 , maxrate=6M, bufsize=12M"
+                .parse::<RateControl>()
+                .unwrap(),
+            RateControl::Crf {
+                crf: Crf(18),
+                vbv: Some(Vbv {
+                    maxrate: 6000,
+                    bufsize: 12000,
+                }),
+            }
+        );
+
+        assert!("crf:52".parse::<RateControl>().is_err());
+        assert!("crf:20,maxrate=6M".parse::<RateControl>().is_err());
+        assert!("crf".parse::<RateControl>().is_err());
+    }
+
+    #[test]
+    fn parse_cqp() {
+        assert_eq!(
+            "cqp:0".parse::<RateControl>().unwrap(),
+            RateControl::Cqp(Qp(0))
+        );
+        assert_eq!(
+            "cqp:69".parse::<RateControl>().unwrap(),
+            RateControl::Cqp(Qp(69))
+        );
+
+        assert!("cqp:70".parse::<RateControl>().is_err());
+        assert!("cqp:20,maxrate=6M,bufsize=12M"
+            .parse::<RateControl>()
+            .is_err());
+    }
+
+    #[test]
+    fn parse_bitrates() {
+        assert_eq!(
+            "abr:4500k".parse::<RateControl>().unwrap(),
+            RateControl::Abr {
+                bitrate: 4500,
+                vbv:     None,
+            }
+        );
+        assert_eq!(
+            "cbr:6M".parse::<RateControl>().unwrap(),
+            RateControl::Cbr {
+                bitrate: 6000,
+                bufsize: 6000,
+            }
+        );
+        assert_eq!(
+            "cbr:6M,bufsize=3M".parse::<RateControl>().unwrap(),
+            RateControl::Cbr {
+                bitrate: 6000,
+                bufsize: 3000,
+            }
+        );
+
+        assert!("abr:6M,maxrate=4M,bufsize=8M"
+            .parse::<RateControl>()
+            .is_err());
+        assert!("abr:0".parse::<RateControl>().is_err());
+        assert!("cbr:0k".parse::<RateControl>().is_err());
+        assert!("cbr:6M,maxrate=6M".parse::<RateControl>().is_err());
+        assert!("cbr:6M,minrate=6M".parse::<RateControl>().is_err());
+        assert!("vbr:6M".parse::<RateControl>().is_err());
+    }
+
+    #[test]
+    fn display_round_trip() {
+        for s in [
+            "crf:20",
+            "crf:18,maxrate=6000k,bufsize=12000k",
+            "cqp:10",
+            "abr:4500k,maxrate=6000k,bufsize=9000k",
+            "cbr:6000k,bufsize=3000k",
+        ] {
+            assert_eq!(s.parse::<RateControl>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn cbr_args() {
+        assert_eq!(
+            "cbr:6M".parse::<RateControl>().unwrap().args(),
+            vec![
+                "-b:v",
+                "6000k",
+                "-minrate",
+                "6000k",
+                "-maxrate",
+                "6000k",
+                "-bufsize",
+                "6000k",
+                "-x264-params",
+                "nal-hrd=cbr"
+            ]
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use std::{fmt, ops::RangeInclusive};
+
+use anyhow::Context;
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd, clap::ArgEnum)]
+pub enum X265Preset {
+    Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    Medium,
+    Slow,
+    Slower,
+    Veryslow,
+    Placebo,
+}
+
+impl fmt::Display for X265Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            X265Preset::Ultrafast => "ultrafast",
+            X265Preset::Superfast => "superfast",
+            X265Preset::Veryfast => "veryfast",
+            X265Preset::Faster => "faster",
+            X265Preset::Fast => "fast",
+            X265Preset::Medium => "medium",
+            X265Preset::Slow => "slow",
+            X265Preset::Slower => "slower",
+            X265Preset::Veryslow => "veryslow",
+            X265Preset::Placebo => "placebo",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd, clap::ArgEnum)]
+pub enum X265Tune {
+    Psnr,
+    Ssim,
+    Grain,
+    Animation,
+    FastDecode,
+    ZeroLatency,
+}
+
+impl fmt::Display for X265Tune {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            X265Tune::Psnr => "psnr",
+            X265Tune::Ssim => "ssim",
+            X265Tune::Grain => "grain",
+            X265Tune::Animation => "animation",
+            X265Tune::FastDecode => "fastdecode",
+            X265Tune::ZeroLatency => "zerolatency",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Hash, Ord, PartialOrd)]
+pub struct Crf(pub u8);
+
+const VALID_RANGE: RangeInclusive<u8> = 0..=51;
+impl Crf {
+    pub fn parse(s: &str) -> anyhow::Result<Crf> {
+        let p: u8 = s.parse().context("crf is not a number")?;
+        if VALID_RANGE.contains(&p) {
+            Ok(Crf(p))
+        } else {
+            anyhow::bail!(
+                "crf out of range: valid range is {} to {}",
+                VALID_RANGE.start(),
+                VALID_RANGE.end()
+            )
+        }
+    }
+}